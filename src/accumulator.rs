@@ -6,7 +6,6 @@
 use std::cmp;
 use std::io;
 use std::io::{BufRead, Read};
-use std::iter::{repeat, Iterator};
 
 #[derive(Debug, Clone)]
 pub struct AccReader<R> {
@@ -22,8 +21,7 @@ impl<R: Read> AccReader<R> {
     }
 
     pub fn with_capacity(cap: usize, inner: R) -> AccReader<R> {
-        let mut buf = Vec::with_capacity(cap);
-        buf.extend(repeat(0).take(cap));
+        let buf = vec![0; cap];
         AccReader {
             inner,
             buf,
@@ -57,7 +55,7 @@ impl<R: Read> AccReader<R> {
                 self.buf[i] = self.buf[self.pos + i];
             }
         }
-        self.cap = self.cap - self.pos;
+        self.cap -= self.pos;
         self.pos = 0;
     }
 
@@ -74,6 +72,23 @@ impl<R: Read> AccReader<R> {
     pub fn capacity(&self) -> usize {
         self.cap - self.pos
     }
+
+    /// size of the internal buffer
+    pub fn buffer_size(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// true if the internal buffer cannot accept more data
+    pub fn is_full(&self) -> bool {
+        self.pos == 0 && self.cap == self.buf.len()
+    }
+
+    /// grows the internal buffer to `size` bytes, keeping its content
+    pub fn grow(&mut self, size: usize) {
+        if size > self.buf.len() {
+            self.buf.resize(size, 0);
+        }
+    }
 }
 
 impl<R: Read> Read for AccReader<R> {
@@ -115,10 +130,8 @@ impl<R: Read> BufRead for AccReader<R> {
     fn fill_buf(&mut self) -> io::Result<&[u8]> {
         //println!("fillbuf current: {:?}", str::from_utf8(&self.buf[self.pos..self.cap]).unwrap());
         if self.pos == 0 && self.cap == self.buf.len() {
-            Err(io::Error::new(
-                io::ErrorKind::Interrupted,
-                "buffer completely filled",
-            ))
+            // not `Interrupted`: `read_to_end` and `io::copy` would retry forever
+            Err(io::Error::other("buffer completely filled"))
         } else {
             self.reset_buffer_position();
             //println!("buffer reset ended");
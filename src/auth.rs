@@ -0,0 +1,418 @@
+//! credentials for the client
+//!
+//! `Basic` and `Bearer` credentials are sent with every request. `Digest`
+//! credentials (RFC 7616) are used once the server sent a challenge with a
+//! `401` response: the client answers it by sending the request again, and
+//! uses the same nonce for the next requests
+
+use crate::util;
+use http::header::HeaderValue;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+#[derive(Clone)]
+pub enum Credentials {
+    Basic { user: String, password: String },
+    Bearer(String),
+    Digest { user: String, password: String },
+}
+
+impl Credentials {
+    pub fn basic(user: &str, password: &str) -> Credentials {
+        Credentials::Basic {
+            user: user.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    pub fn bearer(token: &str) -> Credentials {
+        Credentials::Bearer(token.to_string())
+    }
+
+    pub fn digest(user: &str, password: &str) -> Credentials {
+        Credentials::Digest {
+            user: user.to_string(),
+            password: password.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            Credentials::Basic { user, .. } => write!(f, "Basic({:?})", user),
+            Credentials::Bearer(_) => write!(f, "Bearer"),
+            Credentials::Digest { user, .. } => write!(f, "Digest({:?})", user),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Algorithm {
+    Md5,
+    Md5Sess,
+    Sha256,
+    Sha256Sess,
+}
+
+impl Algorithm {
+    fn parse(name: &str) -> Option<Algorithm> {
+        match name.to_ascii_uppercase().as_str() {
+            "MD5" => Some(Algorithm::Md5),
+            "MD5-SESS" => Some(Algorithm::Md5Sess),
+            "SHA-256" => Some(Algorithm::Sha256),
+            "SHA-256-SESS" => Some(Algorithm::Sha256Sess),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Algorithm::Md5 => "MD5",
+            Algorithm::Md5Sess => "MD5-sess",
+            Algorithm::Sha256 => "SHA-256",
+            Algorithm::Sha256Sess => "SHA-256-sess",
+        }
+    }
+
+    fn is_sha256(self) -> bool {
+        matches!(self, Algorithm::Sha256 | Algorithm::Sha256Sess)
+    }
+
+    fn hash(self, data: &str) -> String {
+        let digest = if self.is_sha256() {
+            Sha256::digest(data.as_bytes()).to_vec()
+        } else {
+            Md5::digest(data.as_bytes()).to_vec()
+        };
+        digest.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+/// the last `Digest` challenge of the server, reused until it sends
+/// another one
+#[derive(Debug, Clone)]
+struct DigestChallenge {
+    realm: String,
+    nonce: String,
+    opaque: Option<String>,
+    algorithm: Algorithm,
+    // qop=auth was offered, otherwise the RFC 2069 computation is used
+    qop_auth: bool,
+    // requests sent with this nonce
+    nonce_count: u32,
+}
+
+impl DigestChallenge {
+    fn parse(params: &[(String, String)]) -> Option<DigestChallenge> {
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.clone())
+        };
+
+        let algorithm = match param("algorithm") {
+            Some(algorithm) => Algorithm::parse(&algorithm)?,
+            None => Algorithm::Md5,
+        };
+        let qop_auth = match param("qop") {
+            Some(qop) => {
+                if !qop
+                    .split(',')
+                    .any(|q| q.trim().eq_ignore_ascii_case("auth"))
+                {
+                    // only auth-int is offered
+                    return None;
+                }
+                true
+            }
+            None => false,
+        };
+
+        Some(DigestChallenge {
+            realm: param("realm").unwrap_or_default(),
+            nonce: param("nonce")?,
+            opaque: param("opaque"),
+            algorithm,
+            qop_auth,
+            nonce_count: 0,
+        })
+    }
+
+    fn authorization(
+        &mut self,
+        user: &str,
+        password: &str,
+        method: &http::Method,
+        uri: &str,
+        cnonce: &str,
+    ) -> String {
+        self.nonce_count += 1;
+        let nc = format!("{:08x}", self.nonce_count);
+        let hash = |data: String| self.algorithm.hash(&data);
+
+        let mut ha1 = hash(format!("{}:{}:{}", user, self.realm, password));
+        if matches!(self.algorithm, Algorithm::Md5Sess | Algorithm::Sha256Sess) {
+            ha1 = hash(format!("{}:{}:{}", ha1, self.nonce, cnonce));
+        }
+        let ha2 = hash(format!("{}:{}", method.as_str(), uri));
+        let response = if self.qop_auth {
+            hash(format!(
+                "{}:{}:{}:{}:auth:{}",
+                ha1, self.nonce, nc, cnonce, ha2
+            ))
+        } else {
+            hash(format!("{}:{}:{}", ha1, self.nonce, ha2))
+        };
+
+        let mut header = format!(
+            "Digest username=\"{}\", realm=\"{}\", uri=\"{}\", algorithm={}, nonce=\"{}\"",
+            quote(user),
+            quote(&self.realm),
+            quote(uri),
+            self.algorithm.as_str(),
+            quote(&self.nonce)
+        );
+        if self.qop_auth {
+            header.push_str(&format!(", nc={}, cnonce=\"{}\", qop=auth", nc, cnonce));
+        }
+        header.push_str(&format!(", response=\"{}\"", response));
+        if let Some(opaque) = &self.opaque {
+            header.push_str(&format!(", opaque=\"{}\"", quote(opaque)));
+        }
+        header
+    }
+}
+
+fn quote(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// 128 random bits, in hexadecimal
+fn cnonce() -> Option<String> {
+    let bytes = util::random::<16>()?;
+    Some(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// parses the challenges of `WWW-Authenticate` headers, as a scheme and
+/// its parameters. Token68 values, as in `Negotiate abc==`, are skipped
+fn parse_challenges(value: &str) -> Vec<(String, Vec<(String, String)>)> {
+    let mut challenges: Vec<(String, Vec<(String, String)>)> = Vec::new();
+    let mut rest = value;
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+        if rest.is_empty() {
+            break;
+        }
+
+        let end = rest
+            .find(|c: char| c == '=' || c == ',' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let token = &rest[..end];
+        let after = rest[end..].trim_start_matches([' ', '\t']);
+
+        if !after.starts_with('=') || challenges.is_empty() {
+            // a new challenge
+            challenges.push((token.to_string(), Vec::new()));
+            rest = after;
+            if after.starts_with('=') {
+                // token68 of a first challenge without scheme, skip it
+                rest = after.trim_start_matches('=');
+            }
+            continue;
+        }
+
+        let after = after[1..].trim_start_matches([' ', '\t']);
+        let (value, remaining) = if let Some(quoted) = after.strip_prefix('"') {
+            let mut value = String::new();
+            let mut chars = quoted.char_indices();
+            let mut end = quoted.len();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\\' => {
+                        if let Some((_, escaped)) = chars.next() {
+                            value.push(escaped);
+                        }
+                    }
+                    '"' => {
+                        end = i + 1;
+                        break;
+                    }
+                    c => value.push(c),
+                }
+            }
+            (value, &quoted[end..])
+        } else {
+            let end = after.find(',').unwrap_or(after.len());
+            (after[..end].trim().to_string(), &after[end..])
+        };
+
+        if let Some((_, params)) = challenges.last_mut() {
+            params.push((token.to_string(), value));
+        }
+        rest = remaining;
+    }
+
+    challenges
+}
+
+/// credentials of a client, and the state of Digest authentication
+#[derive(Debug, Clone)]
+pub(crate) struct Authenticator {
+    credentials: Credentials,
+    // credentials are only sent to this origin
+    origin: url::Origin,
+    digest: Option<DigestChallenge>,
+}
+
+impl Authenticator {
+    pub(crate) fn new(credentials: Credentials, url: &url::Url) -> Authenticator {
+        Authenticator {
+            credentials,
+            origin: url.origin(),
+            digest: None,
+        }
+    }
+
+    /// value of the `Authorization` header for a request to `url`, if the
+    /// credentials can be sent to it
+    pub(crate) fn authorization(
+        &mut self,
+        url: &url::Url,
+        method: &http::Method,
+    ) -> Option<HeaderValue> {
+        if url.origin() != self.origin {
+            return None;
+        }
+
+        let mut value = match &self.credentials {
+            Credentials::Basic { user, password } => return Some(util::basic_auth(user, password)),
+            Credentials::Bearer(token) => {
+                HeaderValue::from_str(&format!("Bearer {}", token)).ok()?
+            }
+            Credentials::Digest { user, password } => {
+                let uri = &url[url::Position::BeforePath..url::Position::AfterQuery];
+                let cnonce = cnonce()?;
+                let header = self
+                    .digest
+                    .as_mut()?
+                    .authorization(user, password, method, uri, &cnonce);
+                HeaderValue::from_str(&header).ok()?
+            }
+        };
+        value.set_sensitive(true);
+        Some(value)
+    }
+
+    /// reads the Digest challenges of a `401` response to `url`. Returns
+    /// true if the request can be sent again with the new challenge
+    pub(crate) fn challenge(&mut self, url: &url::Url, headers: &http::HeaderMap) -> bool {
+        if url.origin() != self.origin {
+            return false;
+        }
+        if let Credentials::Basic { .. } | Credentials::Bearer(_) = self.credentials {
+            return false;
+        }
+
+        let mut challenges: Vec<DigestChallenge> = headers
+            .get_all(http::header::WWW_AUTHENTICATE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(parse_challenges)
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("digest"))
+            .filter_map(|(_, params)| DigestChallenge::parse(&params))
+            .collect();
+        // SHA-256 is preferred, in the order of the server otherwise
+        challenges.sort_by_key(|challenge| !challenge.algorithm.is_sha256());
+
+        match challenges.into_iter().next() {
+            Some(challenge) => {
+                self.digest = Some(challenge);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn challenges() {
+        let challenges = parse_challenges(
+            "Newauth realm=\"apps\", type=1, title=\"Login to \\\"apps\\\"\", Basic realm=\"simple\", Negotiate abc==",
+        );
+        assert_eq!(challenges.len(), 3);
+        assert_eq!(challenges[0].0, "Newauth");
+        assert_eq!(
+            challenges[0].1,
+            vec![
+                ("realm".to_string(), "apps".to_string()),
+                ("type".to_string(), "1".to_string()),
+                ("title".to_string(), "Login to \"apps\"".to_string()),
+            ]
+        );
+        assert_eq!(challenges[1].0, "Basic");
+        assert_eq!(challenges[2].0, "Negotiate");
+    }
+
+    // examples of RFC 7616 section 3.9.1, with qop=auth
+    #[test]
+    fn rfc_7616_examples() {
+        let header = "Digest realm=\"http-auth@example.org\", qop=\"auth, auth-int\", \
+                      algorithm=SHA-256, nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", \
+                      opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"";
+        let params = parse_challenges(header).remove(0).1;
+        let mut challenge = DigestChallenge::parse(&params).unwrap();
+        let authorization = challenge.authorization(
+            "Mufasa",
+            "Circle of Life",
+            &http::Method::GET,
+            "/dir/index.html",
+            "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ",
+        );
+        assert!(authorization.contains(
+            "response=\"753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1\""
+        ));
+        assert!(authorization.contains("nc=00000001"));
+
+        let params = parse_challenges(&header.replace("SHA-256", "MD5"))
+            .remove(0)
+            .1;
+        let mut challenge = DigestChallenge::parse(&params).unwrap();
+        let authorization = challenge.authorization(
+            "Mufasa",
+            "Circle of Life",
+            &http::Method::GET,
+            "/dir/index.html",
+            "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ",
+        );
+        assert!(authorization.contains("response=\"8ca523f5e9506fed4657c9700eebdbec\""));
+    }
+
+    #[test]
+    fn origin() {
+        let url = url::Url::parse("https://example.com/private").unwrap();
+        let mut auth = Authenticator::new(Credentials::bearer("token"), &url);
+        assert_eq!(
+            auth.authorization(&url, &http::Method::GET).unwrap(),
+            "Bearer token"
+        );
+        let other = url::Url::parse("https://example.org/").unwrap();
+        assert!(auth.authorization(&other, &http::Method::GET).is_none());
+        let http = url::Url::parse("http://example.com/private").unwrap();
+        assert!(auth.authorization(&http, &http::Method::GET).is_none());
+    }
+
+    #[test]
+    fn client_nonces() {
+        let (a, b) = (cnonce().unwrap(), cnonce().unwrap());
+        assert_eq!(a.len(), 32);
+        assert!(a.bytes().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(a, b);
+    }
+}
@@ -1,15 +1,31 @@
 use crate::accumulator::AccReader;
+use crate::pool::Checkin;
 use std::fmt::Debug;
 use std::io::{self, BufRead, Read, Write};
-use log::error;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct Body<Stream: Read + Write + Debug> {
-    pub(crate) stream: AccReader<Stream>,
+    // only taken out in `into_inner` and `drop`
+    pub(crate) stream: Option<AccReader<Stream>>,
     pub(crate) length: Length,
     pub(crate) at_eof: bool,
+    // the connection can be used for another request once the body is read
+    pub(crate) keep_alive: bool,
+    // where to store the connection once the body is read
+    pub(crate) pool: Option<Checkin<Stream>>,
+    // reads fail after this point
+    pub(crate) deadline: Option<Instant>,
+    // the CRLF after the data of the current chunk was not read yet
+    pub(crate) chunk_end_pending: bool,
+    // on the server, gives the rest of the request back to the connection
+    pub(crate) handback: Option<Handback<Stream>>,
 }
 
+/// where a request body goes when the server's handler drops it
+pub(crate) type Handback<Stream> = Arc<Mutex<Option<Body<Stream>>>>;
+
 #[derive(Debug, Clone)]
 pub enum Length {
     None,
@@ -17,11 +33,186 @@ pub enum Length {
     ContentLength(usize),
     // remaining size in the current chunk
     Chunked(usize),
+    // no framing: the body ends when the peer closes the connection
+    UntilEof,
 }
 
 impl<Stream: Read + Write + Debug> Body<Stream> {
-    pub fn into_inner(self) -> AccReader<Stream> {
-        self.stream
+    pub(crate) fn new(stream: AccReader<Stream>, length: Length, at_eof: bool) -> Self {
+        Body {
+            stream: Some(stream),
+            length,
+            at_eof,
+            keep_alive: false,
+            pool: None,
+            deadline: None,
+            chunk_end_pending: false,
+            handback: None,
+        }
+    }
+
+    /// returns the underlying reader. The connection will not be
+    /// given back to the pool
+    pub fn into_inner(mut self) -> AccReader<Stream> {
+        self.pool = None;
+        self.handback = None;
+        self.stream.take().expect("the stream is only taken once")
+    }
+
+    /// true if the whole message was read
+    pub fn is_complete(&self) -> bool {
+        match self.length {
+            Length::None | Length::ContentLength(0) => true,
+            Length::UntilEof => self.at_eof,
+            _ => false,
+        }
+    }
+
+    /// true if the whole message was read and the connection can be
+    /// used for another request
+    pub fn is_reusable(&self) -> bool {
+        self.keep_alive
+            && !self.at_eof
+            && !matches!(self.length, Length::UntilEof)
+            && self.is_complete()
+            && self
+                .stream
+                .as_ref()
+                .is_some_and(|stream| stream.buffer().is_empty())
+    }
+
+    // still there until the body is dropped or turned into its stream
+    fn reader(&mut self) -> &mut AccReader<Stream> {
+        self.stream.as_mut().expect("the stream is only taken once")
+    }
+
+    // reads more data from the connection, unless the deadline has passed
+    fn fill_stream(&mut self) -> io::Result<&[u8]> {
+        if let Some(deadline) = self.deadline {
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "request deadline exceeded",
+                ));
+            }
+        }
+
+        self.reader().fill_buf()
+    }
+
+    // reads the CRLF that ends the data of a chunk
+    fn read_chunk_end(&mut self) -> io::Result<()> {
+        while self.reader().buffer().len() < 2 {
+            if self.at_eof || self.fill_stream()?.is_empty() {
+                self.at_eof = true;
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "missing chunk end",
+                ));
+            }
+        }
+
+        if self.reader().buffer()[..2] != b"\r\n"[..] {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid chunk end",
+            ));
+        }
+
+        self.reader().consume(2);
+        self.chunk_end_pending = false;
+        Ok(())
+    }
+
+    // refills the internal buffer if it is empty, returns false at EOF
+    fn fill_until_eof(&mut self) -> io::Result<bool> {
+        if self.reader().buffer().is_empty() {
+            if self.at_eof {
+                return Ok(false);
+            }
+
+            if self.fill_stream()?.is_empty() {
+                self.at_eof = true;
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    // reads the trailer section after the last chunk, up to the final empty line
+    fn finish_chunked(&mut self) -> io::Result<()> {
+        loop {
+            if let Some(pos) = self.reader().buffer().windows(2).position(|w| w == b"\r\n") {
+                self.reader().consume(pos + 2);
+                if pos == 0 {
+                    break;
+                }
+                continue;
+            }
+
+            if self.at_eof || self.fill_stream()?.is_empty() {
+                self.at_eof = true;
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "missing end of the chunked trailer",
+                ));
+            }
+        }
+
+        self.length = Length::None;
+        Ok(())
+    }
+}
+
+impl<Stream: Read + Write + Debug + Clone> Clone for Body<Stream> {
+    fn clone(&self) -> Self {
+        Body {
+            stream: self.stream.clone(),
+            length: self.length.clone(),
+            at_eof: self.at_eof,
+            keep_alive: false,
+            pool: None,
+            deadline: self.deadline,
+            chunk_end_pending: self.chunk_end_pending,
+            handback: None,
+        }
+    }
+}
+
+impl<Stream: Read + Write + Debug> Drop for Body<Stream> {
+    fn drop(&mut self) {
+        let reusable = self.is_reusable();
+        let stream = match self.stream.take() {
+            Some(stream) => stream,
+            // given away by `into_inner`
+            None => return,
+        };
+
+        if let Some(handback) = self.handback.take() {
+            let rest = Body {
+                stream: Some(stream),
+                length: self.length.clone(),
+                at_eof: self.at_eof,
+                keep_alive: false,
+                pool: None,
+                deadline: self.deadline,
+                chunk_end_pending: self.chunk_end_pending,
+                handback: None,
+            };
+            if let Ok(mut slot) = handback.lock() {
+                *slot = Some(rest);
+            }
+            return;
+        }
+
+        if let Some(checkin) = self.pool.take() {
+            if reusable {
+                checkin
+                    .pool
+                    .checkin(checkin.key, stream.into_inner(), checkin.keep_alive);
+            }
+        }
     }
 }
 
@@ -38,16 +229,25 @@ impl<Stream: Read + Write + Debug> Read for Body<Stream> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         let (length, res) = match self.length {
             Length::None => return Ok(0),
+            Length::UntilEof => {
+                if !self.fill_until_eof()? {
+                    return Ok(0);
+                }
+
+                let written = (&mut buf[..]).write(self.reader().buffer())?;
+                self.reader().consume(written);
+                (Length::UntilEof, Ok(written))
+            }
             Length::ContentLength(sz) => {
                 if sz == 0 {
                     return Ok(0);
                 }
 
-                if self.stream.buffer().len() == 0 {
+                if self.reader().buffer().is_empty() {
                     if self.at_eof {
                         return Ok(0);
                     } else {
-                        let data = self.stream.fill_buf()?;
+                        let data = self.fill_stream()?;
 
                         if data.is_empty() {
                             self.at_eof = true;
@@ -57,20 +257,24 @@ impl<Stream: Read + Write + Debug> Read for Body<Stream> {
                 }
 
                 let bound = std::cmp::min(sz, buf.len());
-                let internal_bound = std::cmp::min(bound, self.stream.buffer().len());
+                let internal_bound = std::cmp::min(bound, self.reader().buffer().len());
 
-                let written = (&mut buf[..bound]).write(&self.stream.buffer()[..internal_bound])?;
-                self.stream.consume(written);
+                let written = (&mut buf[..bound]).write(&self.reader().buffer()[..internal_bound])?;
+                self.reader().consume(written);
                 (Length::ContentLength(sz - written), Ok(written))
             }
             Length::Chunked(mut sz) => {
                 if sz == 0 {
-                    if self.stream.buffer().is_empty() {
+                    if self.chunk_end_pending {
+                        self.read_chunk_end()?;
+                    }
+
+                    if self.reader().buffer().is_empty() {
                         if self.at_eof {
                             return Ok(0);
                         }
 
-                        let data = self.stream.fill_buf()?;
+                        let data = self.fill_stream()?;
 
                         if data.is_empty() {
                             self.at_eof = true;
@@ -79,16 +283,13 @@ impl<Stream: Read + Write + Debug> Read for Body<Stream> {
                     }
 
                     let (parsed, chunk_size) = loop {
-                        match httparse::parse_chunk_size(self.stream.buffer()) {
+                        match httparse::parse_chunk_size(self.reader().buffer()) {
                             Err(_invalid_chunk_size) => {
-                                return Err(io::Error::new(
-                                    io::ErrorKind::Other,
-                                    "invalid chunk size",
-                                ));
+                                return Err(io::Error::other("invalid chunk size"));
                             }
                             Ok(status) => {
                                 if status.is_partial() {
-                                    let data = self.stream.fill_buf()?;
+                                    let data = self.fill_stream()?;
 
                                     if data.is_empty() {
                                         self.at_eof = true;
@@ -101,32 +302,32 @@ impl<Stream: Read + Write + Debug> Read for Body<Stream> {
                             }
                         }
                     };
-                    self.stream.consume(parsed);
+                    self.reader().consume(parsed);
                     sz = chunk_size as usize;
                 }
 
                 //if it is still zero, it was the last chunk
                 if sz == 0 {
-                    //FIXME:we might want to parse the last \r\n
+                    self.finish_chunked()?;
                     return Ok(0);
                 }
 
                 let mut index = 0usize;
                 loop {
-                    let bound = std::cmp::min(sz - index, (&buf[index..]).len());
+                    let bound = std::cmp::min(sz - index, buf[index..].len());
                     if bound == 0 {
                         break;
                     }
 
-                    if bound > self.stream.buffer().len() {
+                    if bound > self.reader().buffer().len() {
                         //println!("refilling (bound = {}, buffer len: {}, internal buffer len = {})",
                         //bound, buf.len(),
-                        //self.stream.buffer().len());
+                        //self.reader().buffer().len());
                         if self.at_eof {
                             return Ok(0);
                         }
 
-                        let data = self.stream.fill_buf()?;
+                        let data = self.fill_stream()?;
 
                         if data.is_empty() {
                             self.at_eof = true;
@@ -137,22 +338,18 @@ impl<Stream: Read + Write + Debug> Read for Body<Stream> {
                     }
 
                     // leaving two bytes to check for \r\n
-                    let internal_bound = std::cmp::min(bound, self.stream.buffer().len());
-                    //println!("remaining chunk size: {}, buffer len: {}, internal buffer len: {}, bound: {}, internal bound: {}", sz - index, buf.len(), self.stream.buffer().len(), bound, internal_bound);
+                    let internal_bound = std::cmp::min(bound, self.reader().buffer().len());
+                    //println!("remaining chunk size: {}, buffer len: {}, internal buffer len: {}, bound: {}, internal bound: {}", sz - index, buf.len(), self.reader().buffer().len(), bound, internal_bound);
 
                     let written = (&mut buf[index..index + bound])
-                        .write(&self.stream.buffer()[..internal_bound])?;
-                    //println!("wrote:{:?}", std::str::from_utf8(&self.stream.buffer()[..written]));
-                    self.stream.consume(written);
+                        .write(&self.reader().buffer()[..internal_bound])?;
+                    //println!("wrote:{:?}", std::str::from_utf8(&self.reader().buffer()[..written]));
+                    self.reader().consume(written);
                     index += written;
                 }
 
                 if sz == index {
-                    if &self.stream.buffer()[..2] == &b"\r\n"[..] {
-                        self.stream.consume(2);
-                    } else {
-                        return Err(io::Error::new(io::ErrorKind::Other, "invalid chunk end"));
-                    }
+                    self.chunk_end_pending = true;
                 }
 
                 //println!(" ==> read {} bytes of chunk", index);
@@ -169,18 +366,25 @@ impl<Stream: Read + Write + Debug> Read for Body<Stream> {
 
 impl<Stream: Read+Write+Debug> BufRead for Body<Stream> {
     fn fill_buf(&mut self) -> io::Result<&[u8]> {
-        let (length, res) = match self.length {
+        let (length, available) = match self.length {
             Length::None => return Ok(&b""[..]),
+            Length::UntilEof => {
+                if !self.fill_until_eof()? {
+                    return Ok(&b""[..]);
+                }
+
+                (Length::UntilEof, self.reader().buffer().len())
+            }
             Length::ContentLength(sz) => {
                 if sz == 0 {
                     return Ok(&b""[..]);
                 }
 
-                if self.stream.buffer().len() == 0 {
+                if self.reader().buffer().is_empty() {
                     if self.at_eof {
                         return Ok(&b""[..]);
                     } else {
-                        let data = self.stream.fill_buf()?;
+                        let data = self.fill_stream()?;
 
                         if data.is_empty() {
                             self.at_eof = true;
@@ -189,17 +393,22 @@ impl<Stream: Read+Write+Debug> BufRead for Body<Stream> {
                     }
                 }
 
-                (Length::ContentLength(sz), &self.stream.buffer()[..sz])
+                let min = std::cmp::min(sz, self.reader().buffer().len());
+                (Length::ContentLength(sz), min)
             },
             Length::Chunked(mut sz) => {
                 // we need to parse a chunk header
                 if sz == 0 {
-                    if self.stream.buffer().is_empty() {
+                    if self.chunk_end_pending {
+                        self.read_chunk_end()?;
+                    }
+
+                    if self.reader().buffer().is_empty() {
                         if self.at_eof {
                             return Ok(&b""[..]);
                         }
 
-                        let data = self.stream.fill_buf()?;
+                        let data = self.fill_stream()?;
 
                         if data.is_empty() {
                             self.at_eof = true;
@@ -208,16 +417,13 @@ impl<Stream: Read+Write+Debug> BufRead for Body<Stream> {
                     }
 
                     let (parsed, chunk_size) = loop {
-                        match httparse::parse_chunk_size(self.stream.buffer()) {
+                        match httparse::parse_chunk_size(self.reader().buffer()) {
                             Err(_invalid_chunk_size) => {
-                                return Err(io::Error::new(
-                                    io::ErrorKind::Other,
-                                    "invalid chunk size",
-                                ));
+                                return Err(io::Error::other("invalid chunk size"));
                             }
                             Ok(status) => {
                                 if status.is_partial() {
-                                    let data = self.stream.fill_buf()?;
+                                    let data = self.fill_stream()?;
 
                                     if data.is_empty() {
                                         self.at_eof = true;
@@ -230,33 +436,33 @@ impl<Stream: Read+Write+Debug> BufRead for Body<Stream> {
                             }
                         }
                     };
-                    self.stream.consume(parsed);
+                    self.reader().consume(parsed);
                     sz = chunk_size as usize;
                 }
 
                 //if it is still zero, it was the last chunk
                 if sz == 0 {
-                    //FIXME:we might want to parse the last \r\n
+                    self.finish_chunked()?;
                     return Ok(&b""[..]);
                 }
 
-                if self.stream.buffer().len() == 0 {
+                if self.reader().buffer().is_empty() {
                     if self.at_eof {
-                        (Length::Chunked(sz), &b""[..])
+                        (Length::Chunked(sz), 0)
                     } else {
-                        let data = self.stream.fill_buf()?;
+                        let data = self.fill_stream()?;
 
                         if data.is_empty() {
                             self.at_eof = true;
-                            (Length::Chunked(sz), &b""[..])
+                            (Length::Chunked(sz), 0)
                         } else {
-                            let min = std::cmp::min(sz, self.stream.buffer().len());
-                            (Length::Chunked(sz), &self.stream.buffer()[..min])
+                            let min = std::cmp::min(sz, self.reader().buffer().len());
+                            (Length::Chunked(sz), min)
                         }
                     }
                 } else {
-                    let min = std::cmp::min(sz, self.stream.buffer().len());
-                    (Length::Chunked(sz), &self.stream.buffer()[..min])
+                    let min = std::cmp::min(sz, self.reader().buffer().len());
+                    (Length::Chunked(sz), min)
                 }
             }
 
@@ -264,13 +470,14 @@ impl<Stream: Read+Write+Debug> BufRead for Body<Stream> {
 
         self.length = length;
 
-        Ok(res)
+        Ok(&self.reader().buffer()[..available])
     }
 
     fn consume(&mut self, amt: usize) {
-        self.stream.consume(amt);
+        self.reader().consume(amt);
         self.length = match self.length {
             Length::None => Length::None,
+            Length::UntilEof => Length::UntilEof,
             Length::ContentLength(sz) => {
                 if sz >= amt {
                     Length::ContentLength(sz - amt)
@@ -280,33 +487,9 @@ impl<Stream: Read+Write+Debug> BufRead for Body<Stream> {
             }
             Length::Chunked(sz) => {
                 if sz >= amt {
-                    if sz == amt {
-                        if self.stream.buffer().is_empty() {
-                            if self.at_eof {
-                                return;
-                            }
-
-                            let data = match self.stream.fill_buf() {
-                                Ok(data) => data,
-                                Err(e) => {
-                                    error!("could not fill buffer: {:?}", e);
-                                    return;
-                                }
-                            };
-
-                            if data.is_empty() {
-                                self.at_eof = true;
-                                return;
-                            }
-                        }
-
-                        if self.stream.buffer().len() >= 2 && &self.stream.buffer()[..2] == &b"\r\n"[..] {
-                            self.stream.consume(2);
-                        } else {
-                            error!("could not parse chunk end");
-                            //FIXME: we might need a call to fill_buf here
-                            panic!("could not parse chunk end");
-                        }
+                    // the CRLF is read before the next chunk header
+                    if sz == amt && amt > 0 {
+                        self.chunk_end_pending = true;
                     }
                     Length::Chunked(sz - amt)
                 } else {
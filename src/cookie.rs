@@ -0,0 +1,679 @@
+//! cookie storage for the client, following RFC 6265
+//!
+//! the `CookieJar` stores the cookies of `Set-Cookie` headers and gives the
+//! `Cookie` header for each request. Cookies are refused for public
+//! suffixes like `com` or `co.uk`, from a copy of the public suffix list
+//! bundled with the crate. A newer list can be loaded with
+//! `public_suffix_list`.
+//!
+//! Jars can be saved to and loaded from the Netscape `cookies.txt` format
+//! used by curl and wget
+
+use log::warn;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    /// lower case, without leading dot
+    pub domain: String,
+    /// sent only to `domain`, not to its subdomains
+    pub host_only: bool,
+    pub path: String,
+    /// `None` for session cookies
+    pub expires: Option<SystemTime>,
+    /// sent only over https
+    pub secure: bool,
+    pub http_only: bool,
+    /// stored, but not enforced: the client has no notion of site
+    pub same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    /// parses a `Set-Cookie` header received from `url`. Returns `None` if
+    /// the cookie is invalid or cannot be set by this URL
+    pub fn parse(set_cookie: &str, url: &url::Url) -> Option<Cookie> {
+        Cookie::parse_with(
+            set_cookie,
+            url,
+            &PublicSuffixList::bundled(),
+            SystemTime::now(),
+        )
+    }
+
+    fn parse_with(
+        set_cookie: &str,
+        url: &url::Url,
+        suffixes: &PublicSuffixList,
+        now: SystemTime,
+    ) -> Option<Cookie> {
+        let host = url.host_str()?.trim_end_matches('.').to_ascii_lowercase();
+        let mut parts = set_cookie.split(';');
+        let pair = parts.next()?;
+        let equal = pair.find('=')?;
+        let (name, value) = (pair[..equal].trim(), pair[equal + 1..].trim());
+        if name.is_empty() {
+            return None;
+        }
+
+        let mut cookie = Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(url.path()),
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        };
+
+        let mut max_age = None;
+        let mut domain = None;
+        for attribute in parts {
+            let (key, value) = match attribute.find('=') {
+                Some(i) => (&attribute[..i], attribute[i + 1..].trim()),
+                None => (attribute, ""),
+            };
+            match key.trim().to_ascii_lowercase().as_str() {
+                "expires" => {
+                    if let Some(date) = crate::util::parse_http_date(value) {
+                        cookie.expires = Some(date);
+                    }
+                }
+                "max-age" => {
+                    if let Ok(seconds) = value.parse::<i64>() {
+                        max_age = Some(seconds);
+                    }
+                }
+                "domain" if !value.is_empty() => {
+                    domain = Some(value.trim_start_matches('.').to_ascii_lowercase());
+                }
+                "path" if value.starts_with('/') => cookie.path = value.to_string(),
+                "secure" => cookie.secure = true,
+                "httponly" => cookie.http_only = true,
+                "samesite" => {
+                    cookie.same_site = match value.to_ascii_lowercase().as_str() {
+                        "strict" => Some(SameSite::Strict),
+                        "lax" => Some(SameSite::Lax),
+                        "none" => Some(SameSite::None),
+                        _ => None,
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Max-Age takes precedence over Expires
+        if let Some(seconds) = max_age {
+            cookie.expires = Some(if seconds <= 0 {
+                UNIX_EPOCH
+            } else {
+                now.checked_add(Duration::from_secs(seconds as u64))
+                    .unwrap_or_else(far_future)
+            });
+        }
+
+        if let Some(domain) = domain {
+            if suffixes.is_public_suffix(&domain) {
+                // a public suffix can only set cookies for itself
+                if domain != host {
+                    return None;
+                }
+            } else if !domain_matches(&host, &domain) {
+                return None;
+            } else {
+                cookie.domain = domain;
+                cookie.host_only = false;
+            }
+        }
+
+        Some(cookie)
+    }
+
+    fn is_expired(&self, now: SystemTime) -> bool {
+        matches!(self.expires, Some(expires) if expires <= now)
+    }
+
+    // the cookie can be sent with a request to this URL
+    fn matches(&self, url: &url::Url) -> bool {
+        let host = match url.host_str() {
+            Some(host) => host.trim_end_matches('.').to_ascii_lowercase(),
+            None => return false,
+        };
+
+        let domain = if self.host_only {
+            host == self.domain
+        } else {
+            domain_matches(&host, &self.domain)
+        };
+        domain && path_matches(url.path(), &self.path) && (!self.secure || url.scheme() == "https")
+    }
+}
+
+// RFC 6265 section 5.1.3
+fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host.ends_with(domain)
+            && host[..host.len() - domain.len()].ends_with('.')
+            && host.parse::<std::net::Ipv4Addr>().is_err()
+            && !host.starts_with('['))
+}
+
+// RFC 6265 section 5.1.4
+fn path_matches(path: &str, cookie_path: &str) -> bool {
+    path == cookie_path
+        || (path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || path[cookie_path.len()..].starts_with('/')))
+}
+
+// the directory of the request path
+fn default_path(path: &str) -> String {
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(i) => path[..i].to_string(),
+    }
+}
+
+// used when Max-Age goes past what `SystemTime` can hold: 9999-12-31
+fn far_future() -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(253_402_300_799)
+}
+
+/// rules of the public suffix list, from https://publicsuffix.org/list/
+///
+/// without rules, only top level domains are public suffixes
+#[derive(Debug, Clone)]
+pub struct PublicSuffixList {
+    rules: HashSet<String>,
+    // `*.example` is stored as `example`
+    wildcards: HashSet<String>,
+    exceptions: HashSet<String>,
+}
+
+impl PublicSuffixList {
+    /// parses `public_suffix_list.dat`
+    pub fn parse(list: &str) -> PublicSuffixList {
+        let mut suffixes = PublicSuffixList {
+            rules: HashSet::new(),
+            wildcards: HashSet::new(),
+            exceptions: HashSet::new(),
+        };
+        for line in list.lines() {
+            let rule = match line.split_whitespace().next() {
+                Some(rule) if !rule.starts_with("//") => rule,
+                _ => continue,
+            };
+
+            let (set, name) = if let Some(name) = rule.strip_prefix('!') {
+                (&mut suffixes.exceptions, name)
+            } else if let Some(name) = rule.strip_prefix("*.") {
+                (&mut suffixes.wildcards, name)
+            } else {
+                (&mut suffixes.rules, rule)
+            };
+            // the list is in unicode, hosts in URLs are punycode
+            match url::Host::parse(name) {
+                Ok(url::Host::Domain(name)) => {
+                    set.insert(name);
+                }
+                _ => warn!("ignoring public suffix rule {:?}", rule),
+            }
+        }
+        suffixes
+    }
+
+    /// the list in `data/public_suffix_list.dat`, parsed on first use
+    pub fn bundled() -> Arc<PublicSuffixList> {
+        static BUNDLED: OnceLock<Arc<PublicSuffixList>> = OnceLock::new();
+        BUNDLED
+            .get_or_init(|| {
+                Arc::new(PublicSuffixList::parse(include_str!(
+                    "../data/public_suffix_list.dat"
+                )))
+            })
+            .clone()
+    }
+
+    /// true if `domain`, without trailing dot, is a public suffix
+    pub fn is_public_suffix(&self, domain: &str) -> bool {
+        if self.exceptions.contains(domain) {
+            return false;
+        }
+        match domain.find('.') {
+            // the default rule `*`
+            None => true,
+            Some(i) => self.rules.contains(domain) || self.wildcards.contains(&domain[i + 1..]),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Jar {
+    // in creation order, which is the order of the `Cookie` header for
+    // cookies with paths of the same length
+    cookies: Vec<Cookie>,
+    suffixes: Arc<PublicSuffixList>,
+}
+
+impl Default for Jar {
+    fn default() -> Self {
+        Jar {
+            cookies: Vec::new(),
+            suffixes: PublicSuffixList::bundled(),
+        }
+    }
+}
+
+/// cookies shared by clients
+///
+/// cloning a `CookieJar` gives another handle to the same cookies
+#[derive(Debug, Clone, Default)]
+pub struct CookieJar {
+    inner: Arc<Mutex<Jar>>,
+}
+
+impl CookieJar {
+    pub fn new() -> CookieJar {
+        CookieJar::default()
+    }
+
+    /// public suffixes, in the format of `public_suffix_list.dat`, instead
+    /// of the bundled list
+    pub fn public_suffix_list(self, list: &str) -> CookieJar {
+        if let Ok(mut jar) = self.inner.lock() {
+            jar.suffixes = Arc::new(PublicSuffixList::parse(list));
+        }
+        self
+    }
+
+    /// stores the cookie of a `Set-Cookie` header received from `url`.
+    /// Returns false if it was refused
+    pub fn set_cookie(&self, set_cookie: &str, url: &url::Url) -> bool {
+        let mut jar = match self.inner.lock() {
+            Ok(jar) => jar,
+            Err(_) => return false,
+        };
+        let now = SystemTime::now();
+        let cookie = match Cookie::parse_with(set_cookie, url, &jar.suffixes, now) {
+            Some(cookie) => cookie,
+            None => return false,
+        };
+        jar.insert(cookie, now);
+        true
+    }
+
+    /// stores the cookies of the `Set-Cookie` headers of a response to `url`
+    pub fn store_response_cookies(&self, url: &url::Url, headers: &http::HeaderMap) {
+        for value in headers.get_all(http::header::SET_COOKIE) {
+            match value.to_str() {
+                Ok(set_cookie) => {
+                    self.set_cookie(set_cookie, url);
+                }
+                Err(_) => warn!("ignoring Set-Cookie header that is not valid UTF-8"),
+            }
+        }
+    }
+
+    /// value of the `Cookie` header for a request to `url`, with the
+    /// cookies with the longest paths first
+    pub fn cookie_header(&self, url: &url::Url) -> Option<http::header::HeaderValue> {
+        let mut jar = self.inner.lock().ok()?;
+        let now = SystemTime::now();
+        jar.cookies.retain(|cookie| !cookie.is_expired(now));
+
+        let mut cookies: Vec<&Cookie> = jar.cookies.iter().filter(|c| c.matches(url)).collect();
+        if cookies.is_empty() {
+            return None;
+        }
+        // stable, so creation order is kept for equal lengths
+        cookies.sort_by_key(|cookie| std::cmp::Reverse(cookie.path.len()));
+
+        let header = cookies
+            .iter()
+            .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+            .collect::<Vec<_>>()
+            .join("; ");
+        let mut value = http::header::HeaderValue::from_str(&header).ok()?;
+        value.set_sensitive(true);
+        Some(value)
+    }
+
+    /// the cookies that did not expire
+    pub fn cookies(&self) -> Vec<Cookie> {
+        let now = SystemTime::now();
+        match self.inner.lock() {
+            Ok(jar) => jar
+                .cookies
+                .iter()
+                .filter(|cookie| !cookie.is_expired(now))
+                .cloned()
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut jar) = self.inner.lock() {
+            jar.cookies.clear();
+        }
+    }
+
+    /// reads a Netscape `cookies.txt` file
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<CookieJar> {
+        let jar = CookieJar::new();
+        jar.read_netscape(BufReader::new(File::open(path)?))?;
+        Ok(jar)
+    }
+
+    /// writes the cookies, session cookies included, to a Netscape
+    /// `cookies.txt` file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        self.write_netscape(&mut file)?;
+        file.flush()
+    }
+
+    /// adds the cookies of a Netscape `cookies.txt` file. Invalid lines are
+    /// skipped. An expiration date of 0 is a session cookie
+    pub fn read_netscape<R: BufRead>(&self, reader: R) -> io::Result<()> {
+        let now = SystemTime::now();
+        let mut cookies = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            let (line, http_only) = match line.strip_prefix("#HttpOnly_") {
+                Some(line) => (line, true),
+                None => (line.as_str(), false),
+            };
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split('\t').collect();
+            let expires = match fields.get(4).and_then(|e| e.trim().parse::<u64>().ok()) {
+                Some(0) if fields.len() == 7 => None,
+                Some(e) if fields.len() == 7 => {
+                    match UNIX_EPOCH.checked_add(Duration::from_secs(e)) {
+                        Some(expires) => Some(expires),
+                        None => {
+                            warn!(
+                                "ignoring cookies.txt line with invalid expiration {:?}",
+                                line
+                            );
+                            continue;
+                        }
+                    }
+                }
+                _ => {
+                    warn!("ignoring invalid cookies.txt line {:?}", line);
+                    continue;
+                }
+            };
+
+            let cookie = Cookie {
+                name: fields[5].to_string(),
+                value: fields[6].to_string(),
+                domain: fields[0].trim_start_matches('.').to_ascii_lowercase(),
+                host_only: !fields[1].eq_ignore_ascii_case("TRUE"),
+                path: fields[2].to_string(),
+                expires,
+                secure: fields[3].eq_ignore_ascii_case("TRUE"),
+                http_only,
+                same_site: None,
+            };
+            if !cookie.is_expired(now) {
+                cookies.push(cookie);
+            }
+        }
+
+        let mut jar = self
+            .inner
+            .lock()
+            .map_err(|_| io::Error::other("cookie jar poisoned"))?;
+        for cookie in cookies {
+            jar.insert(cookie, now);
+        }
+        Ok(())
+    }
+
+    /// writes the cookies in the Netscape `cookies.txt` format
+    pub fn write_netscape<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "# Netscape HTTP Cookie File")?;
+        for cookie in self.cookies() {
+            let expires = cookie
+                .expires
+                .and_then(|e| e.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |e| e.as_secs());
+            let bool_field = |b| if b { "TRUE" } else { "FALSE" };
+            writeln!(
+                writer,
+                "{}{}{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                if cookie.http_only { "#HttpOnly_" } else { "" },
+                if cookie.host_only { "" } else { "." },
+                cookie.domain,
+                bool_field(!cookie.host_only),
+                cookie.path,
+                bool_field(cookie.secure),
+                expires,
+                cookie.name,
+                cookie.value
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Jar {
+    // replaces the cookie with the same name, domain and path, or removes
+    // it if the new one is already expired
+    fn insert(&mut self, cookie: Cookie, now: SystemTime) {
+        let existing = self.cookies.iter().position(|c| {
+            c.name == cookie.name && c.domain == cookie.domain && c.path == cookie.path
+        });
+
+        match (existing, cookie.is_expired(now)) {
+            (Some(i), true) => {
+                self.cookies.remove(i);
+            }
+            (Some(i), false) => self.cookies[i] = cookie,
+            (None, true) => {}
+            (None, false) => self.cookies.push(cookie),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(s: &str) -> url::Url {
+        url::Url::parse(s).unwrap()
+    }
+
+    #[test]
+    fn attributes() {
+        let now = SystemTime::now();
+        let suffixes = PublicSuffixList::parse("");
+        let cookie = Cookie::parse_with(
+            "sid=abc; Domain=.Example.com; Path=/app; Expires=Wed, 21 Oct 2015 07:28:00 GMT; \
+             Max-Age=60; Secure; HttpOnly; SameSite=Lax",
+            &url("https://www.example.com/login"),
+            &suffixes,
+            now,
+        )
+        .unwrap();
+        assert_eq!(cookie.name, "sid");
+        assert_eq!(cookie.value, "abc");
+        assert_eq!(cookie.domain, "example.com");
+        assert!(!cookie.host_only);
+        assert_eq!(cookie.path, "/app");
+        assert_eq!(cookie.expires, Some(now + Duration::from_secs(60)));
+        assert!(cookie.secure && cookie.http_only);
+        assert_eq!(cookie.same_site, Some(SameSite::Lax));
+
+        let cookie =
+            Cookie::parse_with("a=b", &url("http://example.com/dir/page"), &suffixes, now).unwrap();
+        assert!(cookie.host_only);
+        assert_eq!(cookie.path, "/dir");
+        assert_eq!(cookie.expires, None);
+
+        let cookie = Cookie::parse_with(
+            "a=b; Max-Age=9223372036854775807",
+            &url("http://example.com/"),
+            &suffixes,
+            now,
+        )
+        .unwrap();
+        assert_eq!(cookie.expires, Some(far_future()));
+
+        let cookie = Cookie::parse_with(
+            "a=b; expires=Sun, 06 Nov 1994 08:49:37 GMT",
+            &url("http://example.com/"),
+            &suffixes,
+            now,
+        )
+        .unwrap();
+        assert_eq!(
+            cookie.expires,
+            Some(UNIX_EPOCH + Duration::from_secs(784111777))
+        );
+
+        for (set_cookie, from) in &[
+            ("a=b; Domain=other.com", "http://example.com/"),
+            ("a=b; Domain=com", "http://example.com/"),
+            ("a=b; Domain=co.uk", "http://example.co.uk/"),
+            ("a=b; Domain=0.1", "http://127.0.0.1/"),
+            ("no value", "http://example.com/"),
+        ] {
+            let suffixes = PublicSuffixList::parse("// comment\nuk\nco.uk\n*.ck\n!www.ck\n");
+            assert_eq!(
+                Cookie::parse_with(set_cookie, &url(from), &suffixes, now),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn public_suffixes() {
+        let list = PublicSuffixList::parse("// comment\ncom\nco.uk\n*.ck\n!www.ck\n公司.cn\n");
+        assert!(list.is_public_suffix("com"));
+        assert!(list.is_public_suffix("co.uk"));
+        assert!(list.is_public_suffix("anything.ck"));
+        assert!(!list.is_public_suffix("www.ck"));
+        assert!(list.is_public_suffix("xn--55qx5d.cn"));
+        assert!(!list.is_public_suffix("example.com"));
+        assert!(list.is_public_suffix("localhost"));
+
+        let bundled = PublicSuffixList::bundled();
+        assert!(bundled.is_public_suffix("co.uk"));
+        assert!(bundled.is_public_suffix("github.io"));
+        assert!(!bundled.is_public_suffix("example.co.uk"));
+        let jar = CookieJar::new();
+        let from = url("http://example.co.uk/");
+        assert!(!jar.set_cookie("a=b; Domain=co.uk", &from));
+        assert!(jar.set_cookie("a=b; Domain=example.co.uk", &from));
+        assert!(Cookie::parse("a=b; Domain=co.uk", &from).is_none());
+    }
+
+    #[test]
+    fn matching() {
+        let jar = CookieJar::new();
+        let base = url("http://www.example.com/app/login");
+        assert!(jar.set_cookie("host=1", &base));
+        assert!(jar.set_cookie("domain=2; Domain=example.com; Path=/", &base));
+        assert!(jar.set_cookie("secure=3; Secure; Path=/", &base));
+        assert!(jar.set_cookie("deep=4; Path=/app/admin", &base));
+
+        let header = |u: &str| {
+            jar.cookie_header(&url(u))
+                .map(|v| v.to_str().unwrap().to_string())
+        };
+        assert_eq!(
+            header("http://www.example.com/app").as_deref(),
+            Some("host=1; domain=2")
+        );
+        assert_eq!(
+            header("https://www.example.com/app/admin/users").as_deref(),
+            Some("deep=4; host=1; domain=2; secure=3")
+        );
+        assert_eq!(
+            header("http://api.example.com/").as_deref(),
+            Some("domain=2")
+        );
+        assert_eq!(
+            header("http://www.example.com/application"),
+            Some("domain=2".to_string())
+        );
+        assert_eq!(header("http://example.org/"), None);
+
+        assert!(jar.set_cookie("host=5", &base));
+        assert!(jar.set_cookie("domain=gone; Domain=example.com; Path=/; Max-Age=0", &base));
+        assert_eq!(
+            header("http://www.example.com/app").as_deref(),
+            Some("host=5")
+        );
+    }
+
+    #[test]
+    fn netscape_format() {
+        let jar = CookieJar::new();
+        let base = url("https://www.example.com/");
+        jar.set_cookie("session=1", &base);
+        jar.set_cookie(
+            "long=2; Domain=example.com; Max-Age=3600; Secure; HttpOnly",
+            &base,
+        );
+
+        let mut file = Vec::new();
+        jar.write_netscape(&mut file).unwrap();
+        let text = String::from_utf8(file.clone()).unwrap();
+        assert!(text.contains("www.example.com\tFALSE\t/\tFALSE\t0\tsession\t1\n"));
+        assert!(text.contains("#HttpOnly_.example.com\tTRUE\t/\tTRUE\t"));
+
+        let loaded = CookieJar::new();
+        loaded.read_netscape(&file[..]).unwrap();
+        loaded
+            .read_netscape(
+                &b"# comment\n\nbroken line\n.old.com\tTRUE\t/\tFALSE\t1\tx\ty\n\
+                   .far.com\tTRUE\t/\tFALSE\t18446744073709551615\tx\ty\n"[..],
+            )
+            .unwrap();
+        let mut expected = jar.cookies();
+        // seconds are all that is kept of the expiration date
+        for cookie in &mut expected {
+            cookie.expires = cookie.expires.map(|e| {
+                UNIX_EPOCH + Duration::from_secs(e.duration_since(UNIX_EPOCH).unwrap().as_secs())
+            });
+            cookie.same_site = None;
+        }
+        assert_eq!(loaded.cookies(), expected);
+    }
+
+    #[test]
+    fn dates() {
+        let date = |s| {
+            crate::util::parse_http_date(s).map(|d| d.duration_since(UNIX_EPOCH).unwrap().as_secs())
+        };
+        assert_eq!(date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(784111777));
+        assert_eq!(date("Sunday, 06-Nov-94 08:49:37 GMT"), Some(784111777));
+        assert_eq!(date("Sun Nov  6 08:49:37 1994"), Some(784111777));
+        assert_eq!(date("Thu, 29 Feb 2024 00:00:00 GMT"), Some(1709164800));
+        assert_eq!(date("not a date"), None);
+        assert_eq!(date("Sun, 32 Nov 1994 08:49:37 GMT"), None);
+        assert_eq!(date("aa\u{20ac} 1 2020 00:00:00"), None);
+        assert_eq!(date("1 \u{20ac}\u{20ac} 2020 00:00:00"), None);
+    }
+}
@@ -0,0 +1,625 @@
+//! content encodings (feature `compression`)
+//!
+//! `Decoder` wraps a response body sent with `Content-Encoding: gzip`,
+//! `deflate` or `br`, possibly stacked as in `gzip, br`, and reads the
+//! decoded content.
+//!
+//! On the server, `Compression` chooses the responses to compress and the
+//! encoding from the request's `Accept-Encoding`, and `Encoder` compresses
+//! their body
+
+use crate::util;
+use crate::HasLength;
+use flate2::bufread::{GzEncoder, MultiGzDecoder, ZlibDecoder};
+use http::header::{self, HeaderValue};
+use std::io::{self, BufRead, BufReader, Read};
+
+/// value of `Accept-Encoding` for the encodings that `Decoder` supports
+pub const ACCEPT_ENCODING: &str = "gzip, deflate, br";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentEncoding {
+    Gzip,
+    // zlib format, as specified for HTTP
+    Deflate,
+    Brotli,
+}
+
+impl ContentEncoding {
+    /// parses a token of `Content-Encoding` or `Accept-Encoding`
+    pub fn parse(token: &str) -> Option<ContentEncoding> {
+        let token = token.trim();
+        if token.eq_ignore_ascii_case("gzip") || token.eq_ignore_ascii_case("x-gzip") {
+            Some(ContentEncoding::Gzip)
+        } else if token.eq_ignore_ascii_case("deflate") {
+            Some(ContentEncoding::Deflate)
+        } else if token.eq_ignore_ascii_case("br") {
+            Some(ContentEncoding::Brotli)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Deflate => "deflate",
+            ContentEncoding::Brotli => "br",
+        }
+    }
+}
+
+/// encodings listed in the `Content-Encoding` headers, in the order they
+/// were applied. `None` if one of them is not supported
+pub fn content_encodings(headers: &http::HeaderMap) -> Option<Vec<ContentEncoding>> {
+    let mut encodings = Vec::new();
+    for value in headers.get_all(http::header::CONTENT_ENCODING) {
+        for token in value.to_str().ok()?.split(',') {
+            let token = token.trim();
+            if token.is_empty() || token.eq_ignore_ascii_case("identity") {
+                continue;
+            }
+            encodings.push(ContentEncoding::parse(token)?);
+        }
+    }
+    Some(encodings)
+}
+
+/// removes `Content-Encoding` and `Content-Length` from a response whose
+/// body will go through a `Decoder`, and returns the decoder
+pub fn decode_response<R: BufRead>(response: http::Response<R>) -> http::Response<Decoder<R>> {
+    let encodings = match content_encodings(response.headers()) {
+        Some(encodings) => encodings,
+        None => return response.map(Decoder::identity),
+    };
+
+    let (mut parts, body) = response.into_parts();
+    if !encodings.is_empty() {
+        parts.headers.remove(http::header::CONTENT_ENCODING);
+        parts.headers.remove(http::header::CONTENT_LENGTH);
+    }
+    http::Response::from_parts(parts, Decoder::new(body, &encodings))
+}
+
+// one decoder for each encoding, the outermost one was applied first
+enum Stage<R: BufRead> {
+    Identity(R),
+    Gzip(Box<BufReader<MultiGzDecoder<Stage<R>>>>),
+    Deflate(Box<BufReader<ZlibDecoder<Stage<R>>>>),
+    Brotli(Box<BufReader<brotli::Decompressor<Stage<R>>>>),
+}
+
+impl<R: BufRead> Stage<R> {
+    fn get_ref(&self) -> &R {
+        match self {
+            Stage::Identity(r) => r,
+            Stage::Gzip(d) => d.get_ref().get_ref().get_ref(),
+            Stage::Deflate(d) => d.get_ref().get_ref().get_ref(),
+            Stage::Brotli(d) => d.get_ref().get_ref().get_ref(),
+        }
+    }
+
+    fn get_mut(&mut self) -> &mut R {
+        match self {
+            Stage::Identity(r) => r,
+            Stage::Gzip(d) => d.get_mut().get_mut().get_mut(),
+            Stage::Deflate(d) => d.get_mut().get_mut().get_mut(),
+            Stage::Brotli(d) => d.get_mut().get_mut().get_mut(),
+        }
+    }
+
+    fn reader(&mut self) -> &mut dyn BufRead {
+        match self {
+            Stage::Identity(r) => r,
+            Stage::Gzip(d) => d,
+            Stage::Deflate(d) => d,
+            Stage::Brotli(d) => d,
+        }
+    }
+}
+
+impl<R: BufRead> Read for Stage<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader().read(buf)
+    }
+}
+
+impl<R: BufRead> BufRead for Stage<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.reader().fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.reader().consume(amt)
+    }
+}
+
+/// reads the decoded content of a body
+pub struct Decoder<R: BufRead> {
+    stage: Stage<R>,
+    // the end of the encoded data was reached and the rest of the body read
+    done: bool,
+}
+
+impl<R: BufRead> Decoder<R> {
+    /// `encodings` are in the order they were applied, as in the
+    /// `Content-Encoding` header
+    pub fn new(inner: R, encodings: &[ContentEncoding]) -> Decoder<R> {
+        // the last encoding applied is the first one to decode
+        let mut stage = Stage::Identity(inner);
+        for encoding in encodings.iter().rev() {
+            stage = match encoding {
+                ContentEncoding::Gzip => {
+                    Stage::Gzip(Box::new(BufReader::new(MultiGzDecoder::new(stage))))
+                }
+                ContentEncoding::Deflate => {
+                    Stage::Deflate(Box::new(BufReader::new(ZlibDecoder::new(stage))))
+                }
+                ContentEncoding::Brotli => Stage::Brotli(Box::new(BufReader::new(
+                    brotli::Decompressor::new(stage, 4096),
+                ))),
+            };
+        }
+
+        Decoder { stage, done: false }
+    }
+
+    /// passes the content through
+    pub fn identity(inner: R) -> Decoder<R> {
+        Decoder::new(inner, &[])
+    }
+
+    /// true if the body goes through at least one decoder
+    pub fn is_decoding(&self) -> bool {
+        !matches!(self.stage, Stage::Identity(_))
+    }
+
+    /// the encoded body
+    pub fn get_ref(&self) -> &R {
+        self.stage.get_ref()
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        self.stage.get_mut()
+    }
+
+    // the encoded data can end before the body, as with the last chunk of
+    // a chunked body. Reading it lets the connection go back to the pool
+    fn finish(&mut self) -> io::Result<()> {
+        if !self.done {
+            self.done = true;
+            io::copy(self.stage.get_mut(), &mut io::sink())?;
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> std::fmt::Debug for Decoder<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        f.debug_struct("Decoder")
+            .field("decoding", &self.is_decoding())
+            .field("done", &self.done)
+            .finish()
+    }
+}
+
+impl<R: BufRead> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.stage.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            self.finish()?;
+        }
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for Decoder<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.stage.fill_buf()?.is_empty() {
+            self.finish()?;
+        }
+        self.stage.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.stage.consume(amt)
+    }
+}
+
+/// which responses the server compresses
+#[derive(Debug, Clone)]
+pub struct Compression {
+    min_size: usize,
+    content_types: Vec<String>,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression {
+            min_size: 1024,
+            content_types: [
+                "text/",
+                "application/json",
+                "application/javascript",
+                "application/xml",
+                "image/svg+xml",
+            ]
+            .iter()
+            .map(|t| t.to_string())
+            .collect(),
+        }
+    }
+}
+
+impl Compression {
+    /// compresses text, JSON, JavaScript, XML and SVG of at least 1024 bytes
+    pub fn new() -> Compression {
+        Compression::default()
+    }
+
+    /// bodies with a known length below this are sent as is
+    pub fn min_size(mut self, min_size: usize) -> Compression {
+        self.min_size = min_size;
+        self
+    }
+
+    /// media types to compress. Types ending with `/` match all their
+    /// subtypes, as in `text/`
+    pub fn content_types(mut self, types: &[&str]) -> Compression {
+        self.content_types = types.iter().map(|t| t.to_ascii_lowercase()).collect();
+        self
+    }
+
+    // compressing would give a smaller body for clients that accept it
+    fn is_eligible<T: HasLength>(&self, response: &http::Response<T>) -> bool {
+        let headers = response.headers();
+        let content_type = match headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+        {
+            Some(t) => t
+                .split(';')
+                .next()
+                .unwrap_or("")
+                .trim()
+                .to_ascii_lowercase(),
+            None => return false,
+        };
+
+        response.status() != http::StatusCode::PARTIAL_CONTENT
+            && !response.status().is_informational()
+            && response.status() != http::StatusCode::NO_CONTENT
+            && response.status() != http::StatusCode::NOT_MODIFIED
+            && !headers.contains_key(header::CONTENT_ENCODING)
+            && !headers.contains_key(header::CONTENT_RANGE)
+            && !util::has_token(headers.get_all(header::CACHE_CONTROL), b"no-transform")
+            && !matches!(response.body().has_length(), Some(len) if len < self.min_size)
+            && self.content_types.iter().any(|t| {
+                if t.ends_with('/') {
+                    content_type.starts_with(t.as_str())
+                } else {
+                    content_type == *t
+                }
+            })
+    }
+
+    /// compresses the response if it is eligible and `accept_encoding`, the
+    /// header of the request, allows gzip or brotli. The body is then sent
+    /// chunked, and `Vary: Accept-Encoding` is added to eligible responses
+    /// even if they are not compressed
+    pub fn apply<T: BufRead + HasLength>(
+        &self,
+        accept_encoding: Option<&HeaderValue>,
+        mut response: http::Response<T>,
+    ) -> http::Response<Encoder<T>> {
+        if !self.is_eligible(&response) {
+            return response.map(Encoder::identity);
+        }
+
+        let headers = response.headers_mut();
+        let vary = headers.get_all(header::VARY);
+        if !util::has_token(vary.iter(), b"accept-encoding") && !util::has_token(vary, b"*") {
+            headers.append(header::VARY, HeaderValue::from_static("accept-encoding"));
+        }
+
+        let encoding = match accept_encoding
+            .and_then(|v| v.to_str().ok())
+            .and_then(negotiate)
+        {
+            Some(encoding) => encoding,
+            None => return response.map(Encoder::identity),
+        };
+
+        headers.remove(header::CONTENT_LENGTH);
+        headers.insert(
+            header::CONTENT_ENCODING,
+            HeaderValue::from_static(encoding.as_str()),
+        );
+        // the compressed content is another representation
+        if let Some(etag) = headers.get(header::ETAG) {
+            if !etag.as_bytes().starts_with(b"W/") {
+                let mut weak = b"W/".to_vec();
+                weak.extend_from_slice(etag.as_bytes());
+                if let Ok(weak) = HeaderValue::from_bytes(&weak) {
+                    headers.insert(header::ETAG, weak);
+                }
+            }
+        }
+
+        response.map(|body| Encoder::new(body, encoding))
+    }
+}
+
+// brotli or gzip, whichever has the highest q-value, brotli if they are
+// equal. `*` stands for the encodings not listed
+fn negotiate(accept_encoding: &str) -> Option<ContentEncoding> {
+    let mut brotli = None;
+    let mut gzip = None;
+    let mut any = None;
+
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';');
+        let coding = parts.next().unwrap_or("").trim();
+        let q = parts
+            .filter_map(|p| {
+                let (name, value) = p.split_at(p.find('=')?);
+                if name.trim().eq_ignore_ascii_case("q") {
+                    value[1..].trim().parse::<f32>().ok()
+                } else {
+                    None
+                }
+            })
+            .next()
+            .unwrap_or(1.0);
+
+        if coding == "*" {
+            any = Some(q);
+        } else {
+            match ContentEncoding::parse(coding) {
+                Some(ContentEncoding::Brotli) => brotli = Some(q),
+                Some(ContentEncoding::Gzip) => gzip = Some(q),
+                _ => {}
+            }
+        }
+    }
+
+    let brotli = brotli.or(any).unwrap_or(0.0);
+    let gzip = gzip.or(any).unwrap_or(0.0);
+    if brotli > 0.0 && brotli >= gzip {
+        Some(ContentEncoding::Brotli)
+    } else if gzip > 0.0 {
+        Some(ContentEncoding::Gzip)
+    } else {
+        None
+    }
+}
+
+enum Compressor<R: BufRead> {
+    Identity(R),
+    Gzip(Box<BufReader<GzEncoder<R>>>),
+    Brotli(Box<BufReader<brotli::CompressorReader<R>>>),
+}
+
+/// reads the compressed content of a response body. It has no known
+/// length, so the body is sent chunked
+pub struct Encoder<R: BufRead> {
+    compressor: Compressor<R>,
+}
+
+impl<R: BufRead> Encoder<R> {
+    /// only gzip and brotli are used to compress, `Deflate` gives gzip
+    pub fn new(inner: R, encoding: ContentEncoding) -> Encoder<R> {
+        let compressor = match encoding {
+            ContentEncoding::Brotli => Compressor::Brotli(Box::new(BufReader::new(
+                brotli::CompressorReader::new(inner, 4096, 5, 22),
+            ))),
+            ContentEncoding::Gzip | ContentEncoding::Deflate => Compressor::Gzip(Box::new(
+                BufReader::new(GzEncoder::new(inner, flate2::Compression::default())),
+            )),
+        };
+        Encoder { compressor }
+    }
+
+    /// passes the content through
+    pub fn identity(inner: R) -> Encoder<R> {
+        Encoder {
+            compressor: Compressor::Identity(inner),
+        }
+    }
+
+    /// true if the body is compressed
+    pub fn is_encoding(&self) -> bool {
+        !matches!(self.compressor, Compressor::Identity(_))
+    }
+
+    /// the original body
+    pub fn into_inner(self) -> R {
+        match self.compressor {
+            Compressor::Identity(r) => r,
+            Compressor::Gzip(e) => e.into_inner().into_inner(),
+            Compressor::Brotli(e) => e.into_inner().into_inner(),
+        }
+    }
+
+    fn reader(&mut self) -> &mut dyn BufRead {
+        match &mut self.compressor {
+            Compressor::Identity(r) => r,
+            Compressor::Gzip(e) => e,
+            Compressor::Brotli(e) => e,
+        }
+    }
+}
+
+impl<R: BufRead> std::fmt::Debug for Encoder<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        f.debug_struct("Encoder")
+            .field("encoding", &self.is_encoding())
+            .finish()
+    }
+}
+
+impl<R: BufRead + HasLength> HasLength for Encoder<R> {
+    fn has_length(&self) -> Option<usize> {
+        match &self.compressor {
+            Compressor::Identity(r) => r.has_length(),
+            _ => None,
+        }
+    }
+}
+
+impl<R: BufRead> Read for Encoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader().read(buf)
+    }
+}
+
+impl<R: BufRead> BufRead for Encoder<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.reader().fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.reader().consume(amt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use std::io::Write;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn zlib(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn brotli(data: &[u8]) -> Vec<u8> {
+        let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+        encoder.write_all(data).unwrap();
+        encoder.into_inner()
+    }
+
+    #[test]
+    fn stacked_encodings() {
+        let content = b"hello hello hello hello".to_vec();
+        let encoded = brotli(&zlib(&gzip(&content)));
+
+        let response = http::Response::builder()
+            .header("Content-Encoding", "gzip, deflate")
+            .header("Content-Encoding", "br")
+            .header("Content-Length", encoded.len())
+            .body(&encoded[..])
+            .unwrap();
+        let mut response = decode_response(response);
+        assert!(response.headers().get("content-encoding").is_none());
+        assert!(response.headers().get("content-length").is_none());
+
+        let mut decoded = Vec::new();
+        response.body_mut().read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, content);
+
+        let response = http::Response::builder()
+            .header("Content-Encoding", "compress")
+            .body(&b"raw"[..])
+            .unwrap();
+        let response = decode_response(response);
+        assert_eq!(response.headers()["content-encoding"], "compress");
+        assert!(!response.body().is_decoding());
+    }
+
+    #[test]
+    fn reads_the_rest_of_the_body() {
+        let mut body = zlib(b"content");
+        body.extend_from_slice(b"trailing");
+        let mut decoder = Decoder::new(&body[..], &[ContentEncoding::Deflate]);
+
+        let mut decoded = String::new();
+        decoder.read_line(&mut decoded).unwrap();
+        assert_eq!(decoded, "content");
+        assert!(decoder.get_ref().is_empty());
+    }
+
+    #[test]
+    fn negotiation() {
+        assert_eq!(
+            negotiate("gzip, deflate, br"),
+            Some(ContentEncoding::Brotli)
+        );
+        assert_eq!(
+            negotiate("gzip;q=1.0, br;q=0.5"),
+            Some(ContentEncoding::Gzip)
+        );
+        assert_eq!(negotiate("br;q=0, *"), Some(ContentEncoding::Gzip));
+        assert_eq!(negotiate("*;q=0.1"), Some(ContentEncoding::Brotli));
+        assert_eq!(negotiate("deflate, identity"), None);
+        assert_eq!(negotiate("gzip;q=0"), None);
+        assert_eq!(negotiate(""), None);
+    }
+
+    #[test]
+    fn compressed_responses() {
+        let content = "hello ".repeat(400);
+        let response = |content_type: &str| {
+            http::Response::builder()
+                .header("Content-Type", content_type)
+                .header("ETag", "\"v1\"")
+                .body(std::io::Cursor::new(content.clone()))
+                .unwrap()
+        };
+        let gzip = HeaderValue::from_static("gzip");
+        let compression = Compression::new();
+
+        let mut compressed = compression.apply(Some(&gzip), response("text/html; charset=utf-8"));
+        assert_eq!(compressed.headers()["content-encoding"], "gzip");
+        assert_eq!(compressed.headers()["vary"], "accept-encoding");
+        assert_eq!(compressed.headers()["etag"], "W/\"v1\"");
+        assert_eq!(compressed.body().has_length(), None);
+        let mut encoded = Vec::new();
+        compressed.body_mut().read_to_end(&mut encoded).unwrap();
+        assert!(encoded.len() < content.len());
+        let mut decoded = String::new();
+        Decoder::new(&encoded[..], &[ContentEncoding::Gzip])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, content);
+
+        let plain = compression.apply(None, response("application/json"));
+        assert!(!plain.body().is_encoding());
+        assert_eq!(plain.headers()["vary"], "accept-encoding");
+        assert_eq!(plain.body().has_length(), Some(content.len()));
+
+        for skipped in [
+            response("image/png"),
+            http::Response::builder()
+                .header("Content-Type", "text/plain")
+                .body(std::io::Cursor::new("short".to_string()))
+                .unwrap(),
+            {
+                let mut r = response("text/plain");
+                r.headers_mut().insert(
+                    "content-range",
+                    HeaderValue::from_static("bytes 0-2399/5000"),
+                );
+                r
+            },
+            {
+                let mut r = response("text/plain");
+                r.headers_mut()
+                    .insert("content-encoding", HeaderValue::from_static("br"));
+                r
+            },
+        ] {
+            let res = compression.apply(Some(&gzip), skipped);
+            assert!(!res.body().is_encoding());
+            assert!(res.headers().get("vary").is_none());
+        }
+    }
+}
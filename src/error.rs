@@ -7,6 +7,24 @@ pub enum HttpError {
     Io(io::Error),
     Parser(httparse::Error),
     Http(http::Error),
+    Redirect(RedirectError),
+    Timeout { phase: TimeoutPhase },
+    // the connection was closed in the middle of the message head
+    UnexpectedEof,
+    // header value that is not valid UTF-8 or cannot be stored in a `HeaderValue`
+    InvalidHeaderValue,
+    // a request or status line with another version than HTTP/1.0 or HTTP/1.1
+    UnsupportedVersion,
+    // missing or invalid `Location` header, or URL without a host
+    InvalidRedirect,
+    // too many headers, or a header or the whole head over the `ParserConfig` limits
+    HeadersTooLarge,
+    // invalid or conflicting `Content-Length` values, `Content-Length` with
+    // `Transfer-Encoding`, or a request body that does not end with the
+    // chunked encoding: the end of the body is ambiguous
+    InvalidBodyLength,
+    Tls(TlsError),
+    Proxy(ProxyError),
 }
 
 impl From<ResolverError> for HttpError {
@@ -17,6 +35,11 @@ impl From<ResolverError> for HttpError {
 
 impl From<io::Error> for HttpError {
     fn from(e: io::Error) -> Self {
+        // failed TLS handshakes are reported through the stream
+        #[cfg(feature = "tls")]
+        if let Some(e) = crate::tls::handshake_error(&e) {
+            return HttpError::Tls(e);
+        }
         HttpError::Io(e)
     }
 }
@@ -33,6 +56,24 @@ impl From<http::Error> for HttpError {
     }
 }
 
+impl From<RedirectError> for HttpError {
+    fn from(e: RedirectError) -> Self {
+        HttpError::Redirect(e)
+    }
+}
+
+impl From<TlsError> for HttpError {
+    fn from(e: TlsError) -> Self {
+        HttpError::Tls(e)
+    }
+}
+
+impl From<ProxyError> for HttpError {
+    fn from(e: ProxyError) -> Self {
+        HttpError::Proxy(e)
+    }
+}
+
 impl From<url::ParseError> for HttpError {
     fn from(e: url::ParseError) -> Self {
         HttpError::Url(e)
@@ -45,3 +86,48 @@ pub enum ResolverError {
     ConnectionFailed,
     InvalidScheme,
 }
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RedirectError {
+    TooManyRedirects,
+    // redirected from https to http
+    Downgrade,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TlsError {
+    // neither a DNS name nor an IP address
+    InvalidServerName(String),
+    // certificate, key or root certificate that could not be loaded
+    Config(String),
+    // none of the pins matched the SHA-256 hash of the server's public key
+    PinMismatch { presented: [u8; 32] },
+    // the handshake failed, with the rustls error
+    Handshake(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProxyError {
+    // only http:// proxies, or socks5:// for `Socks5Resolver`, are supported
+    UnsupportedScheme,
+    // the proxy answered `CONNECT` with 407
+    AuthenticationRequired,
+    // the proxy answered `CONNECT` with another status than 2xx
+    Refused(http::StatusCode),
+    // the SOCKS server accepted none of our authentication methods, or
+    // rejected the credentials
+    SocksAuthenticationFailed,
+    // the SOCKS server could not connect to the target, with the reply code
+    SocksRefused(u8),
+    // the SOCKS server sent something that is not a SOCKS5 reply
+    SocksProtocol,
+}
+
+/// the part of the request that took too long
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutPhase {
+    Connect,
+    FirstByte,
+    BodyRead,
+    Total,
+}
@@ -6,12 +6,28 @@ extern crate webpki;
 extern crate webpki_roots;
 
 pub mod accumulator;
+pub mod auth;
 pub mod body;
 pub mod client;
+pub mod cookie;
+#[cfg(feature = "compression")]
+pub mod encoding;
 pub mod error;
+pub mod netrc;
+pub mod parser;
+pub mod pool;
+pub mod proxy;
+pub mod redirect;
+pub mod resolver;
+pub mod retry;
 pub mod server;
 pub mod stream;
+pub mod timeout;
+#[cfg(feature = "tls")]
+pub mod tls;
 mod util;
+#[cfg(feature = "tls")]
+mod x509;
 
 use error::*;
 
@@ -31,3 +47,10 @@ impl HasLength for &[u8] {
         Some(self.len())
     }
 }
+
+impl<T: AsRef<[u8]>> HasLength for std::io::Cursor<T> {
+    fn has_length(&self) -> Option<usize> {
+        let len = self.get_ref().as_ref().len() as u64;
+        Some(len.saturating_sub(self.position()) as usize)
+    }
+}
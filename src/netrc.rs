@@ -0,0 +1,255 @@
+//! credentials from a `.netrc` file
+//!
+//! `machine` entries give the `login` and `password` for a host, and the
+//! `default` entry for the other hosts. `account` is ignored, and `macdef`
+//! macros are skipped up to the next empty line. Values can be quoted,
+//! with `\` escapes, and `#` starts a comment.
+//!
+//! The credentials are sent with Basic authentication, over https only
+//! unless `allow_http` is set
+
+use crate::auth::Credentials;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, PartialEq, Eq)]
+struct Login {
+    login: String,
+    password: String,
+}
+
+#[derive(Clone, Default)]
+pub struct Netrc {
+    // host in lower case, with the credentials
+    machines: Vec<(String, Login)>,
+    default: Option<Login>,
+    allow_http: bool,
+}
+
+impl Netrc {
+    pub fn parse(content: &str) -> Netrc {
+        let mut netrc = Netrc::default();
+        let mut tokens = Tokens { rest: content };
+        // the entry being read: `None` for `default`
+        let mut entry: Option<(Option<String>, Login)> = None;
+
+        while let Some(token) = tokens.next() {
+            match token.as_str() {
+                "machine" | "default" => {
+                    netrc.add(entry.take());
+                    let host = if token == "machine" {
+                        match tokens.next() {
+                            Some(host) => Some(host.to_ascii_lowercase()),
+                            None => break,
+                        }
+                    } else {
+                        None
+                    };
+                    let login = Login {
+                        login: String::new(),
+                        password: String::new(),
+                    };
+                    entry = Some((host, login));
+                }
+                "login" | "password" | "account" => {
+                    let value = tokens.next().unwrap_or_default();
+                    if let Some((_, login)) = &mut entry {
+                        match token.as_str() {
+                            "login" => login.login = value,
+                            "password" => login.password = value,
+                            _ => {}
+                        }
+                    }
+                }
+                "macdef" => {
+                    tokens.next();
+                    tokens.skip_macro();
+                }
+                // unknown tokens are ignored
+                _ => {}
+            }
+        }
+        netrc.add(entry);
+
+        netrc
+    }
+
+    fn add(&mut self, entry: Option<(Option<String>, Login)>) {
+        match entry {
+            // the first entry for a host wins
+            Some((Some(host), login)) if !self.machines.iter().any(|(h, _)| *h == host) => {
+                self.machines.push((host, login));
+            }
+            Some((Some(_), _)) => {}
+            Some((None, login)) => {
+                self.default.get_or_insert(login);
+            }
+            None => {}
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Netrc> {
+        Ok(Netrc::parse(&fs::read_to_string(path)?))
+    }
+
+    /// reads the file given by the `NETRC` environment variable, or
+    /// `.netrc` in the home directory
+    pub fn from_home() -> io::Result<Netrc> {
+        let path = std::env::var_os("NETRC")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME")
+                    .or_else(|| std::env::var_os("USERPROFILE"))
+                    .map(|home| Path::new(&home).join(".netrc"))
+            })
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no home directory"))?;
+        Netrc::load(path)
+    }
+
+    /// also send the credentials over plaintext http
+    pub fn allow_http(mut self, allow: bool) -> Netrc {
+        self.allow_http = allow;
+        self
+    }
+
+    /// login and password for the host, from its `machine` entry or the
+    /// `default` one
+    pub fn credentials(&self, host: &str) -> Option<(&str, &str)> {
+        let host = host.to_ascii_lowercase();
+        self.machines
+            .iter()
+            .find(|(h, _)| *h == host)
+            .map(|(_, login)| login)
+            .or(self.default.as_ref())
+            .map(|login| (login.login.as_str(), login.password.as_str()))
+    }
+
+    /// Basic credentials for the host of the URL, if they can be sent to it
+    pub(crate) fn credentials_for(&self, url: &url::Url) -> Option<Credentials> {
+        if url.scheme() != "https" && !(self.allow_http && url.scheme() == "http") {
+            return None;
+        }
+        let (login, password) = self.credentials(url.host_str()?)?;
+        Some(Credentials::basic(login, password))
+    }
+}
+
+impl std::fmt::Debug for Netrc {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        let hosts: Vec<&str> = self.machines.iter().map(|(h, _)| h.as_str()).collect();
+        f.debug_struct("Netrc")
+            .field("machines", &hosts)
+            .field("default", &self.default.is_some())
+            .field("allow_http", &self.allow_http)
+            .finish()
+    }
+}
+
+struct Tokens<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Tokens<'a> {
+    fn next(&mut self) -> Option<String> {
+        loop {
+            self.rest = self.rest.trim_start();
+            if !self.rest.starts_with('#') {
+                break;
+            }
+            let end = self.rest.find('\n').unwrap_or(self.rest.len());
+            self.rest = &self.rest[end..];
+        }
+        if self.rest.is_empty() {
+            return None;
+        }
+
+        let mut token = String::new();
+        if let Some(quoted) = self.rest.strip_prefix('"') {
+            let mut chars = quoted.char_indices();
+            let mut end = quoted.len();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '"' => {
+                        end = i + 1;
+                        break;
+                    }
+                    '\\' => {
+                        if let Some((_, c)) = chars.next() {
+                            token.push(c);
+                        }
+                    }
+                    c => token.push(c),
+                }
+            }
+            self.rest = &quoted[end..];
+        } else {
+            let end = self
+                .rest
+                .find(char::is_whitespace)
+                .unwrap_or(self.rest.len());
+            token.push_str(&self.rest[..end]);
+            self.rest = &self.rest[end..];
+        }
+        Some(token)
+    }
+
+    // a macro goes from the end of the `macdef` line to the next empty line
+    fn skip_macro(&mut self) {
+        let body = match self.rest.find('\n') {
+            Some(start) => &self.rest[start + 1..],
+            None => "",
+        };
+        let mut offset = 0;
+        for line in body.split_inclusive('\n') {
+            offset += line.len();
+            if line.trim().is_empty() {
+                break;
+            }
+        }
+        self.rest = &body[offset..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries() {
+        let netrc = Netrc::parse(
+            "# comment\n\
+             machine example.com login alice password \"s3cret pass\"\n\
+             machine Other.org\n  login bob\n  account ignored\n  password \"a\\\"b\"\n\
+             macdef init\ncd /pub\nmachine evil.com login mallory\n\n\
+             machine example.com login second password x\n\
+             default login anonymous password guest@\n",
+        );
+
+        assert_eq!(
+            netrc.credentials("example.com"),
+            Some(("alice", "s3cret pass"))
+        );
+        assert_eq!(netrc.credentials("OTHER.org"), Some(("bob", "a\"b")));
+        assert_eq!(netrc.credentials("evil.com"), Some(("anonymous", "guest@")));
+
+        let netrc = Netrc::parse("machine example.com login alice");
+        assert_eq!(netrc.credentials("example.com"), Some(("alice", "")));
+        assert_eq!(netrc.credentials("example.org"), None);
+        assert_eq!(Netrc::parse("machine").credentials("machine"), None);
+    }
+
+    #[test]
+    fn schemes() {
+        let netrc = Netrc::parse("machine example.com login alice password secret");
+        let https = url::Url::parse("https://example.com/").unwrap();
+        let http = url::Url::parse("http://example.com/").unwrap();
+
+        assert!(matches!(
+            netrc.credentials_for(&https),
+            Some(Credentials::Basic { ref user, .. }) if user == "alice"
+        ));
+        assert!(netrc.credentials_for(&http).is_none());
+        assert!(netrc.allow_http(true).credentials_for(&http).is_some());
+    }
+}
@@ -0,0 +1,68 @@
+//! limits applied when parsing the head (request or status line and headers)
+//! of a message, on the client and the server side
+
+use crate::accumulator::AccReader;
+use crate::HttpError;
+use std::io::Read;
+
+/// the header array and the buffer start small and grow up to these limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParserConfig {
+    /// maximum number of headers
+    pub max_headers: usize,
+    /// maximum size of the whole head, including the request or status line
+    pub max_head_size: usize,
+    /// maximum size of one header, name and value
+    pub max_header_size: usize,
+}
+
+impl Default for ParserConfig {
+    fn default() -> Self {
+        ParserConfig {
+            max_headers: 100,
+            max_head_size: 64 * 1024,
+            max_header_size: 16 * 1024,
+        }
+    }
+}
+
+impl ParserConfig {
+    /// size of the header array for the first parsing attempt
+    pub(crate) fn initial_headers(&self) -> usize {
+        std::cmp::min(30, self.max_headers)
+    }
+
+    /// size of the read buffer at the start of a message
+    pub(crate) fn initial_buffer(&self) -> usize {
+        std::cmp::min(16384, self.max_head_size)
+    }
+
+    /// size of the header array after httparse returned `TooManyHeaders`
+    pub(crate) fn more_headers(&self, current: usize) -> Result<usize, HttpError> {
+        if current >= self.max_headers {
+            Err(HttpError::HeadersTooLarge)
+        } else {
+            Ok(std::cmp::min(current * 2, self.max_headers))
+        }
+    }
+
+    /// grows the buffer if the partial head filled it
+    pub(crate) fn make_room<R: Read>(&self, stream: &mut AccReader<R>) -> Result<(), HttpError> {
+        if stream.is_full() {
+            let size = stream.buffer_size();
+            if size >= self.max_head_size {
+                return Err(HttpError::HeadersTooLarge);
+            }
+            stream.grow(std::cmp::min(size * 2, self.max_head_size));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_header(&self, header: &httparse::Header) -> Result<(), HttpError> {
+        if header.name.len() + header.value.len() > self.max_header_size {
+            Err(HttpError::HeadersTooLarge)
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,208 @@
+//! connection pool for the client
+//!
+//! idle connections are stored per scheme/host/port and proxy, and handed back
+//! by `Body` once a response was completely read on a keep-alive
+//! connection
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PoolKey {
+    scheme: String,
+    host: String,
+    port: Option<u16>,
+    // connections through a proxy do not go to the server directly
+    proxy: Option<String>,
+}
+
+impl PoolKey {
+    /// key of direct connections to the server of `url`
+    pub fn from_url(url: &url::Url) -> PoolKey {
+        PoolKey {
+            scheme: url.scheme().to_string(),
+            host: url.host_str().unwrap_or("").to_string(),
+            port: url.port_or_known_default(),
+            proxy: None,
+        }
+    }
+
+    /// key of connections to the same server through the proxy at `proxy`
+    pub fn through_proxy(mut self, proxy: &url::Url) -> PoolKey {
+        self.proxy = Some(proxy.to_string());
+        self
+    }
+}
+
+struct Idle<Stream> {
+    stream: Stream,
+    expires: Instant,
+}
+
+struct PoolInner<Stream> {
+    idle: HashMap<PoolKey, Vec<Idle<Stream>>>,
+    max_idle_per_host: usize,
+    idle_timeout: Duration,
+}
+
+/// shared pool of idle connections
+///
+/// cloning a `Pool` gives another handle to the same set of connections
+pub struct Pool<Stream> {
+    inner: Arc<Mutex<PoolInner<Stream>>>,
+}
+
+impl<Stream> Pool<Stream> {
+    pub fn new() -> Pool<Stream> {
+        Pool::with_config(8, Duration::from_secs(90))
+    }
+
+    /// `max_idle_per_host` caps the number of idle connections kept for one
+    /// scheme/host/port, `idle_timeout` is the longest a connection can stay
+    /// idle before being discarded (the server can ask for less with the
+    /// `Keep-Alive: timeout=` header)
+    pub fn with_config(max_idle_per_host: usize, idle_timeout: Duration) -> Pool<Stream> {
+        Pool {
+            inner: Arc::new(Mutex::new(PoolInner {
+                idle: HashMap::new(),
+                max_idle_per_host,
+                idle_timeout,
+            })),
+        }
+    }
+
+    /// takes the most recently used idle connection for this key, if any
+    pub fn checkout(&self, key: &PoolKey) -> Option<Stream> {
+        let mut inner = self.inner.lock().ok()?;
+        let now = Instant::now();
+
+        let list = inner.idle.get_mut(key)?;
+        list.retain(|idle| idle.expires > now);
+        let res = list.pop().map(|idle| idle.stream);
+
+        if list.is_empty() {
+            inner.idle.remove(key);
+        }
+        res
+    }
+
+    /// stores an idle connection. If there are already too many idle
+    /// connections for this key, the oldest one is closed
+    pub fn checkin(&self, key: PoolKey, stream: Stream, keep_alive: Option<Duration>) {
+        let mut inner = match self.inner.lock() {
+            Ok(inner) => inner,
+            Err(_) => return,
+        };
+
+        if inner.max_idle_per_host == 0 {
+            return;
+        }
+
+        let timeout = match keep_alive {
+            Some(t) => std::cmp::min(t, inner.idle_timeout),
+            None => inner.idle_timeout,
+        };
+        let now = Instant::now();
+        let max = inner.max_idle_per_host;
+
+        let list = inner.idle.entry(key).or_default();
+        list.retain(|idle| idle.expires > now);
+        if list.len() >= max {
+            list.remove(0);
+        }
+        list.push(Idle {
+            stream,
+            expires: now + timeout,
+        });
+    }
+
+    /// number of idle connections stored for this key
+    pub fn idle_count(&self, key: &PoolKey) -> usize {
+        self.inner
+            .lock()
+            .map(|inner| inner.idle.get(key).map(|l| l.len()).unwrap_or(0))
+            .unwrap_or(0)
+    }
+}
+
+impl<Stream> Default for Pool<Stream> {
+    fn default() -> Self {
+        Pool::new()
+    }
+}
+
+impl<Stream> Clone for Pool<Stream> {
+    fn clone(&self) -> Self {
+        Pool {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<Stream> std::fmt::Debug for Pool<Stream> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        f.debug_struct("Pool").finish()
+    }
+}
+
+/// what a `Body` needs to give its connection back to the pool
+pub(crate) struct Checkin<Stream> {
+    pub(crate) pool: Pool<Stream>,
+    pub(crate) key: PoolKey,
+    pub(crate) keep_alive: Option<Duration>,
+}
+
+impl<Stream> std::fmt::Debug for Checkin<Stream> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        f.debug_struct("Checkin").field("key", &self.key).finish()
+    }
+}
+
+/// parses the `timeout` parameter of a `Keep-Alive` header
+pub(crate) fn keep_alive_timeout(value: &[u8]) -> Option<Duration> {
+    let value = std::str::from_utf8(value).ok()?;
+    value.split(',').find_map(|param| {
+        let mut it = param.splitn(2, '=');
+        let name = it.next()?.trim();
+        let v = it.next()?.trim();
+        if name.eq_ignore_ascii_case("timeout") {
+            v.parse::<u64>().ok().map(Duration::from_secs)
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checkin_checkout() {
+        let pool: Pool<u32> = Pool::with_config(2, Duration::from_secs(60));
+        let key = PoolKey::from_url(&url::Url::parse("http://example.com/a").unwrap());
+        let other = PoolKey::from_url(&url::Url::parse("https://example.com/a").unwrap());
+
+        pool.checkin(key.clone(), 1, None);
+        pool.checkin(key.clone(), 2, None);
+        pool.checkin(key.clone(), 3, None);
+        assert_eq!(pool.idle_count(&key), 2);
+        assert_eq!(pool.checkout(&other), None);
+        assert_eq!(pool.checkout(&key), Some(3));
+        assert_eq!(pool.checkout(&key), Some(2));
+        assert_eq!(pool.checkout(&key), None);
+
+        pool.checkin(key.clone(), 4, Some(Duration::from_secs(0)));
+        assert_eq!(pool.checkout(&key), None);
+    }
+
+    #[test]
+    fn keep_alive_header() {
+        assert_eq!(
+            keep_alive_timeout(b"timeout=5, max=100"),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(keep_alive_timeout(b"max=100"), None);
+    }
+}
@@ -0,0 +1,180 @@
+//! redirect policy for the client
+//!
+//! decides which redirects are followed, how the method changes on
+//! 301/302/303, and records the chain of visited URLs
+
+use crate::error::RedirectError;
+use http::StatusCode;
+
+/// a redirect the client is about to follow
+#[derive(Debug)]
+pub struct Attempt<'a> {
+    pub status: StatusCode,
+    pub next: &'a url::Url,
+    /// URLs visited so far, starting with the original request
+    pub previous: &'a [url::Url],
+}
+
+enum Kind {
+    None,
+    Limited(usize),
+    Custom(Box<dyn Fn(&Attempt) -> bool + Send + Sync>),
+}
+
+pub struct RedirectPolicy {
+    kind: Kind,
+    rewrite_post: bool,
+    allow_downgrade: bool,
+}
+
+/// URLs visited while following redirects, from the original request to
+/// the final response. It is stored in the response's extensions
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedirectChain(pub Vec<url::Url>);
+
+impl RedirectPolicy {
+    /// redirects are returned to the caller as is
+    pub fn none() -> RedirectPolicy {
+        RedirectPolicy::with_kind(Kind::None)
+    }
+
+    /// follows at most `max` redirects, then returns `RedirectError::TooManyRedirects`
+    pub fn limited(max: usize) -> RedirectPolicy {
+        RedirectPolicy::with_kind(Kind::Limited(max))
+    }
+
+    /// the closure decides if a redirect is followed. If it returns false,
+    /// the redirect response is returned to the caller
+    pub fn custom<F: Fn(&Attempt) -> bool + Send + Sync + 'static>(f: F) -> RedirectPolicy {
+        RedirectPolicy::with_kind(Kind::Custom(Box::new(f)))
+    }
+
+    fn with_kind(kind: Kind) -> RedirectPolicy {
+        RedirectPolicy {
+            kind,
+            rewrite_post: false,
+            allow_downgrade: false,
+        }
+    }
+
+    /// like browsers, turn a POST into a GET without body on 301 and 302
+    /// (303 always does it)
+    pub fn rewrite_post_on_301_302(mut self, rewrite: bool) -> RedirectPolicy {
+        self.rewrite_post = rewrite;
+        self
+    }
+
+    /// follows redirects from https to http URLs (refused by default)
+    pub fn allow_https_downgrade(mut self, allow: bool) -> RedirectPolicy {
+        self.allow_downgrade = allow;
+        self
+    }
+
+    /// returns true if the redirect should be followed
+    pub(crate) fn check(
+        &self,
+        status: StatusCode,
+        next: &url::Url,
+        previous: &[url::Url],
+    ) -> Result<bool, RedirectError> {
+        if let Kind::None = self.kind {
+            return Ok(false);
+        }
+
+        if !self.allow_downgrade
+            && next.scheme() == "http"
+            && previous.last().map(|u| u.scheme()) == Some("https")
+        {
+            return Err(RedirectError::Downgrade);
+        }
+
+        match &self.kind {
+            Kind::None => Ok(false),
+            // `previous` contains the original request
+            Kind::Limited(max) => {
+                if previous.len() > *max {
+                    Err(RedirectError::TooManyRedirects)
+                } else {
+                    Ok(true)
+                }
+            }
+            Kind::Custom(f) => Ok(f(&Attempt {
+                status,
+                next,
+                previous,
+            })),
+        }
+    }
+
+    /// true if the redirected request must be a GET without body
+    pub(crate) fn changes_method(&self, status: StatusCode, method: &http::Method) -> bool {
+        match status {
+            StatusCode::SEE_OTHER => *method != http::Method::HEAD,
+            StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND => {
+                self.rewrite_post && *method == http::Method::POST
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> Self {
+        RedirectPolicy::limited(10)
+    }
+}
+
+impl std::fmt::Debug for RedirectPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        let kind = match self.kind {
+            Kind::None => "None".to_string(),
+            Kind::Limited(max) => format!("Limited({})", max),
+            Kind::Custom(_) => "Custom".to_string(),
+        };
+        f.debug_struct("RedirectPolicy")
+            .field("kind", &kind)
+            .field("rewrite_post", &self.rewrite_post)
+            .field("allow_downgrade", &self.allow_downgrade)
+            .finish()
+    }
+}
+
+pub(crate) fn is_redirect(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::MOVED_PERMANENTLY
+            | StatusCode::FOUND
+            | StatusCode::SEE_OTHER
+            | StatusCode::TEMPORARY_REDIRECT
+            | StatusCode::PERMANENT_REDIRECT
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits() {
+        let a = url::Url::parse("https://example.com/a").unwrap();
+        let b = url::Url::parse("https://example.com/b").unwrap();
+        let insecure = url::Url::parse("http://example.com/b").unwrap();
+        let policy = RedirectPolicy::limited(1);
+        let first = vec![a];
+        let second = vec![first[0].clone(), b.clone()];
+
+        assert_eq!(policy.check(StatusCode::FOUND, &b, &first), Ok(true));
+        assert_eq!(
+            policy.check(StatusCode::FOUND, &b, &second),
+            Err(RedirectError::TooManyRedirects)
+        );
+        assert_eq!(
+            policy.check(StatusCode::FOUND, &insecure, &first),
+            Err(RedirectError::Downgrade)
+        );
+        assert_eq!(
+            RedirectPolicy::none().check(StatusCode::FOUND, &b, &first),
+            Ok(false)
+        );
+    }
+}
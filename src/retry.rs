@@ -0,0 +1,285 @@
+//! retry policy for the client
+//!
+//! requests that failed on the connection, or got a 429, 502, 503 or 504
+//! response, are sent again after a delay that doubles with each attempt.
+//! `Retry-After` replaces that delay when the response has one.
+//!
+//! Only idempotent methods are sent again by default, unless the request
+//! was never sent: then the server cannot have acted on it
+
+use crate::error::ResolverError;
+use crate::util;
+use crate::HttpError;
+use http::{Method, StatusCode};
+use std::io;
+use std::time::{Duration, SystemTime};
+
+/// why an attempt did not give a response to return
+pub(crate) enum Failure<'a> {
+    // the connection could not be opened, the request was not written
+    NotSent(&'a HttpError),
+    // the request was written, but no response came back
+    NoResponse(&'a HttpError),
+    Status(StatusCode, &'a http::HeaderMap),
+}
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    jitter: bool,
+    non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::none()
+    }
+}
+
+impl RetryPolicy {
+    /// requests are sent once
+    pub fn none() -> RetryPolicy {
+        RetryPolicy::new(1)
+    }
+
+    /// sends a request at most `max_attempts` times. The first retry waits
+    /// 100ms, the next ones twice as long as the previous one, up to 30s
+    pub fn new(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+            non_idempotent: false,
+        }
+    }
+
+    /// delay before the first retry, and the longest delay between two
+    /// attempts. Responses asking with `Retry-After` for more than `max`
+    /// are returned to the caller
+    pub fn backoff(mut self, base: Duration, max: Duration) -> RetryPolicy {
+        self.base_delay = base;
+        self.max_delay = max;
+        self
+    }
+
+    /// waits between half and all of the backoff delay, chosen at random,
+    /// so clients that failed together do not come back together. On by
+    /// default
+    pub fn jitter(mut self, jitter: bool) -> RetryPolicy {
+        self.jitter = jitter;
+        self
+    }
+
+    /// also sends POST and PATCH requests again when they may have reached
+    /// the server
+    pub fn retry_non_idempotent(mut self, retry: bool) -> RetryPolicy {
+        self.non_idempotent = retry;
+        self
+    }
+
+    /// delay before the next attempt, or `None` if the failure must be
+    /// returned. `attempt` starts at 1
+    pub(crate) fn delay(
+        &self,
+        attempt: u32,
+        method: &Method,
+        failure: &Failure,
+    ) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+
+        let (transient, sent) = match failure {
+            Failure::NotSent(e) => (is_transient(e), false),
+            Failure::NoResponse(e) => (is_transient(e), true),
+            Failure::Status(status, _) => (is_transient_status(*status), true),
+        };
+        if !transient || (sent && !self.non_idempotent && !is_idempotent(method)) {
+            return None;
+        }
+
+        if let Failure::Status(_, headers) = failure {
+            if let Some(delay) = retry_after(headers, SystemTime::now()) {
+                return Some(delay).filter(|delay| *delay <= self.max_delay);
+            }
+        }
+        Some(self.backoff_delay(attempt))
+    }
+
+    fn backoff_delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let delay = self
+            .base_delay
+            .checked_mul(factor)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+        if self.jitter {
+            delay / 2 + delay.mul_f64(random() / 2.0)
+        } else {
+            delay
+        }
+    }
+}
+
+// connection failures and resets, not timeouts or invalid responses
+fn is_transient(e: &HttpError) -> bool {
+    match e {
+        HttpError::Resolver(ResolverError::ConnectionFailed) => true,
+        HttpError::UnexpectedEof => true,
+        HttpError::Io(e) => matches!(
+            e.kind(),
+            io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::UnexpectedEof
+        ),
+        _ => false,
+    }
+}
+
+fn is_transient_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+// RFC 7231 section 4.2.2
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE
+    )
+}
+
+/// `Retry-After` as a number of seconds or an HTTP date. Dates in the past
+/// give no delay
+fn retry_after(headers: &http::HeaderMap, now: SystemTime) -> Option<Duration> {
+    let value = headers
+        .get(http::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) {
+        return Some(Duration::from_secs(value.parse().unwrap_or(u64::MAX)));
+    }
+    let date = util::parse_http_date(value)?;
+    Some(date.duration_since(now).unwrap_or_default())
+}
+
+// between 0 and 1, or 1 without a random generator: the full delay
+fn random() -> f64 {
+    match util::random::<8>() {
+        Some(bytes) => (u64::from_le_bytes(bytes) >> 11) as f64 / (1u64 << 53) as f64,
+        None => 1.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays() {
+        let policy = RetryPolicy::new(4)
+            .backoff(Duration::from_millis(100), Duration::from_millis(300))
+            .jitter(false);
+        let error = HttpError::UnexpectedEof;
+        let failure = Failure::NoResponse(&error);
+        let delays: Vec<_> = (1..=4)
+            .map(|attempt| policy.delay(attempt, &Method::GET, &failure))
+            .collect();
+        assert_eq!(
+            delays,
+            vec![
+                Some(Duration::from_millis(100)),
+                Some(Duration::from_millis(200)),
+                Some(Duration::from_millis(300)),
+                None
+            ]
+        );
+
+        let policy = policy.jitter(true);
+        for _ in 0..20 {
+            let delay = policy.delay(2, &Method::GET, &failure).unwrap();
+            assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(200));
+        }
+
+        assert!(RetryPolicy::none()
+            .delay(1, &Method::GET, &failure)
+            .is_none());
+        let error = HttpError::Timeout {
+            phase: crate::error::TimeoutPhase::FirstByte,
+        };
+        assert!(policy
+            .delay(1, &Method::GET, &Failure::NoResponse(&error))
+            .is_none());
+    }
+
+    #[test]
+    fn idempotency() {
+        let policy = RetryPolicy::new(2);
+        let refused = HttpError::Resolver(ResolverError::ConnectionFailed);
+        let reset = HttpError::Io(io::ErrorKind::ConnectionReset.into());
+        let headers = http::HeaderMap::new();
+        let unavailable = Failure::Status(StatusCode::SERVICE_UNAVAILABLE, &headers);
+
+        assert!(policy
+            .delay(1, &Method::POST, &Failure::NotSent(&refused))
+            .is_some());
+        assert!(policy
+            .delay(1, &Method::POST, &Failure::NoResponse(&reset))
+            .is_none());
+        assert!(policy.delay(1, &Method::POST, &unavailable).is_none());
+        assert!(policy.delay(1, &Method::PUT, &unavailable).is_some());
+        assert!(policy
+            .delay(
+                1,
+                &Method::GET,
+                &Failure::Status(StatusCode::INTERNAL_SERVER_ERROR, &headers)
+            )
+            .is_none());
+
+        let policy = policy.retry_non_idempotent(true);
+        assert!(policy
+            .delay(1, &Method::POST, &Failure::NoResponse(&reset))
+            .is_some());
+    }
+
+    #[test]
+    fn retry_after_header() {
+        let policy =
+            RetryPolicy::new(2).backoff(Duration::from_millis(100), Duration::from_secs(60));
+        let mut headers = http::HeaderMap::new();
+        headers.insert(http::header::RETRY_AFTER, "120".parse().unwrap());
+        let failure = Failure::Status(StatusCode::TOO_MANY_REQUESTS, &headers);
+        assert_eq!(policy.delay(1, &Method::GET, &failure), None);
+        headers.insert(http::header::RETRY_AFTER, " 7 ".parse().unwrap());
+        let failure = Failure::Status(StatusCode::TOO_MANY_REQUESTS, &headers);
+        assert_eq!(
+            policy.delay(1, &Method::GET, &failure),
+            Some(Duration::from_secs(7))
+        );
+
+        let now = util::parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+        headers.insert(
+            http::header::RETRY_AFTER,
+            "Wed, 21 Oct 2015 07:28:30 GMT".parse().unwrap(),
+        );
+        assert_eq!(retry_after(&headers, now), Some(Duration::from_secs(30)));
+        headers.insert(
+            http::header::RETRY_AFTER,
+            "Wed, 21 Oct 2015 07:27:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(retry_after(&headers, now), Some(Duration::from_secs(0)));
+        headers.insert(http::header::RETRY_AFTER, "soon".parse().unwrap());
+        assert_eq!(retry_after(&headers, now), None);
+    }
+}
@@ -1,26 +1,557 @@
 use crate::accumulator::AccReader;
-use crate::body::{Body, Length};
+use crate::body::{Body, Handback, Length};
+#[cfg(feature = "compression")]
+use crate::encoding::Compression;
+use crate::parser::ParserConfig;
+#[cfg(feature = "tls")]
+use crate::stream::HttpStream;
+use crate::timeout::{self, SetTimeout, SetTimeoutFn};
 use crate::util;
+use http::header::HeaderValue;
 use crate::HasLength;
 use crate::HttpError;
+use log::error;
 use std::fmt::Debug;
-use std::io::{BufRead, BufWriter, Read, Write};
+use std::io::{self, BufRead, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-pub struct Server;
+/// sources of incoming connections for `Server::serve`
+pub trait Listener {
+    type Stream: Read + Write + Debug + Send + 'static;
+
+    fn accept(&self) -> io::Result<Self::Stream>;
+
+    /// limits how long reads and writes on an accepted stream block. The
+    /// default implementation does nothing, so `Server::timeout` is not
+    /// applied
+    fn set_timeout(stream: &Self::Stream, timeout: Option<Duration>) -> io::Result<()> {
+        let _ = (stream, timeout);
+        Ok(())
+    }
+}
+
+impl Listener for TcpListener {
+    type Stream = TcpStream;
+
+    fn accept(&self) -> io::Result<TcpStream> {
+        TcpListener::accept(self).map(|(stream, _)| stream)
+    }
+
+    fn set_timeout(stream: &TcpStream, timeout: Option<Duration>) -> io::Result<()> {
+        timeout::set_both(stream, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl Listener for std::os::unix::net::UnixListener {
+    type Stream = std::os::unix::net::UnixStream;
+
+    fn accept(&self) -> io::Result<Self::Stream> {
+        std::os::unix::net::UnixListener::accept(self).map(|(stream, _)| stream)
+    }
+
+    fn set_timeout(stream: &Self::Stream, timeout: Option<Duration>) -> io::Result<()> {
+        timeout::set_both(stream, timeout)
+    }
+}
+
+/// accepts TLS connections from another listener, with a configuration
+/// built by `tls::TlsServerConfig`
+#[cfg(feature = "tls")]
+pub struct TlsListener<L> {
+    listener: L,
+    config: Arc<rustls::ServerConfig>,
+}
+
+#[cfg(feature = "tls")]
+impl<L: Listener> TlsListener<L> {
+    pub fn new(listener: L, config: Arc<rustls::ServerConfig>) -> TlsListener<L> {
+        TlsListener { listener, config }
+    }
+}
+
+#[cfg(feature = "tls")]
+impl<L: Listener> Listener for TlsListener<L> {
+    type Stream = HttpStream<L::Stream>;
+
+    // the handshake is done by the thread handling the connection
+    fn accept(&self) -> io::Result<Self::Stream> {
+        let stream = self.listener.accept()?;
+        Ok(HttpStream::tls_server(stream, &self.config))
+    }
+
+    fn set_timeout(stream: &Self::Stream, timeout: Option<Duration>) -> io::Result<()> {
+        L::set_timeout(stream.get_ref(), timeout)
+    }
+}
+
+/// HTTP/1.x server: accepts connections from a `Listener` and answers
+/// each request with a handler, on a pool of threads.
+///
+/// The handler must not keep the request body after returning: the
+/// connection is given back to the server when the body is dropped
+#[derive(Debug, Clone)]
+pub struct Server {
+    threads: usize,
+    max_connections: usize,
+    parser: ParserConfig,
+    timeout: Option<Duration>,
+    #[cfg(feature = "compression")]
+    compression: Option<Compression>,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Server {
+            threads: 8,
+            max_connections: 1024,
+            parser: ParserConfig::default(),
+            timeout: Some(Duration::from_secs(60)),
+            #[cfg(feature = "compression")]
+            compression: None,
+        }
+    }
+}
+
+impl Server {
+    pub fn new() -> Server {
+        Server::default()
+    }
+
+    /// number of threads handling connections
+    pub fn threads(mut self, threads: usize) -> Server {
+        self.threads = std::cmp::max(threads, 1);
+        self
+    }
+
+    /// connections accepted but not closed yet, including the ones waiting
+    /// for a thread. When it is reached, new connections are not accepted
+    pub fn max_connections(mut self, max: usize) -> Server {
+        self.max_connections = std::cmp::max(max, 1);
+        self
+    }
+
+    pub fn parser_config(mut self, config: ParserConfig) -> Server {
+        self.parser = config;
+        self
+    }
+
+    /// longest wait for the head of the next request, idle time included,
+    /// then for each read or write until the response is sent. The
+    /// connection is closed when it expires. 60 seconds by default, `None`
+    /// waits forever.
+    ///
+    /// It is applied through `Listener::set_timeout`
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Server {
+        self.timeout = timeout;
+        self
+    }
+
+    /// compresses the responses according to `Accept-Encoding`
+    #[cfg(feature = "compression")]
+    pub fn compression(mut self, compression: Compression) -> Server {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// accepts connections until the listener fails
+    pub fn serve<L, F, T>(&self, listener: L, handler: F) -> io::Result<()>
+    where
+        L: Listener,
+        F: Fn(http::Request<Body<L::Stream>>) -> http::Response<T> + Send + Sync + 'static,
+        T: BufRead + Read + HasLength + Debug,
+    {
+        let handler = Arc::new(handler);
+        let connections = Arc::new((Mutex::new(0usize), Condvar::new()));
+        let (sender, receiver) = mpsc::channel::<L::Stream>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let mut workers = Vec::new();
+        for _ in 0..self.threads {
+            let receiver = receiver.clone();
+            let handler = handler.clone();
+            let connections = connections.clone();
+            let server = self.clone();
+
+            workers.push(thread::spawn(move || loop {
+                let stream = match receiver.lock().map(|r| r.recv()) {
+                    Ok(Ok(stream)) => stream,
+                    // the accept loop stopped
+                    _ => break,
+                };
+
+                let _slot = Slot(&connections);
+                // a panicking handler only loses its connection, not the thread
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    server.serve_timeout(stream, &*handler, L::set_timeout)
+                }));
+                match result {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => error!("connection error: {:?}", e),
+                    Err(_) => error!("the handler panicked, closing the connection"),
+                }
+            }));
+        }
+
+        let res = loop {
+            {
+                let (count, freed) = &*connections;
+                let mut count = count.lock().unwrap_or_else(|e| e.into_inner());
+                while *count >= self.max_connections {
+                    count = freed.wait(count).unwrap_or_else(|e| e.into_inner());
+                }
+                *count += 1;
+            }
+
+            match listener.accept() {
+                Ok(stream) => {
+                    if sender.send(stream).is_err() {
+                        break Ok(());
+                    }
+                }
+                Err(e) => break Err(e),
+            }
+        };
+
+        drop(sender);
+        for worker in workers {
+            let _ = worker.join();
+        }
+        res
+    }
+
+    /// answers the requests of one connection until it is closed. The
+    /// timeout is not applied: use `serve_connection_timeout` for streams
+    /// that implement `SetTimeout`
+    pub fn serve_connection<S, F, T>(&self, stream: S, handler: &F) -> Result<(), HttpError>
+    where
+        S: Read + Write + Debug,
+        F: Fn(http::Request<Body<S>>) -> http::Response<T>,
+        T: BufRead + Read + HasLength + Debug,
+    {
+        let mut connection = Connection::with_config(stream, self.parser);
+        self.run(&mut connection, handler)
+    }
+
+    /// like `serve_connection`, with the timeout
+    pub fn serve_connection_timeout<S, F, T>(&self, stream: S, handler: &F) -> Result<(), HttpError>
+    where
+        S: Read + Write + Debug + SetTimeout,
+        F: Fn(http::Request<Body<S>>) -> http::Response<T>,
+        T: BufRead + Read + HasLength + Debug,
+    {
+        self.serve_timeout(stream, handler, timeout::set_both::<S>)
+    }
+
+    fn serve_timeout<S, F, T>(
+        &self,
+        stream: S,
+        handler: &F,
+        set_timeout: SetTimeoutFn<S>,
+    ) -> Result<(), HttpError>
+    where
+        S: Read + Write + Debug,
+        F: Fn(http::Request<Body<S>>) -> http::Response<T>,
+        T: BufRead + Read + HasLength + Debug,
+    {
+        let mut connection = Connection::with_config(stream, self.parser);
+        connection.timeout = self.timeout;
+        connection.set_timeout = Some(set_timeout);
+        self.run(&mut connection, handler)
+    }
+
+    fn run<S, F, T>(&self, connection: &mut Connection<S>, handler: &F) -> Result<(), HttpError>
+    where
+        S: Read + Write + Debug,
+        F: Fn(http::Request<Body<S>>) -> http::Response<T>,
+        T: BufRead + Read + HasLength + Debug,
+    {
+        #[cfg(feature = "compression")]
+        if let Some(compression) = &self.compression {
+            connection.set_compression(compression.clone());
+        }
+        while let Some(request) = connection.next_request()? {
+            let response = handler(request);
+            connection.respond(response)?;
+        }
+        Ok(())
+    }
+}
+
+// a connection counted against `max_connections`, given back when dropped
+struct Slot<'a>(&'a (Mutex<usize>, Condvar));
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        let (count, freed) = self.0;
+        let mut count = count.lock().unwrap_or_else(|e| e.into_inner());
+        *count -= 1;
+        freed.notify_one();
+    }
+}
+
+/// server side of one connection: requests are read and answered one at a
+/// time, in order. The read buffer is kept from one request to the next, so
+/// pipelined requests are not lost.
+///
+/// The body of a request must be dropped before answering it. What was not
+/// read of it is skipped before parsing the next request
+#[derive(Debug)]
+pub struct Connection<Stream: Read + Write + Debug> {
+    // None once the connection must be closed
+    stream: Option<AccReader<Stream>>,
+    parser: ParserConfig,
+    // the request waiting for a response
+    current: Option<Current<Stream>>,
+    timeout: Option<Duration>,
+    // only set if the stream implements `SetTimeout`
+    set_timeout: Option<SetTimeoutFn<Stream>>,
+    #[cfg(feature = "compression")]
+    compression: Option<Compression>,
+}
+
+#[derive(Debug)]
+struct Current<Stream: Read + Write + Debug> {
+    method: http::Method,
+    version: http::Version,
+    close: bool,
+    handback: Handback<Stream>,
+    // HTTP/1.0 clients cannot read the chunked bodies of compressed responses
+    #[cfg(feature = "compression")]
+    accept_encoding: Option<HeaderValue>,
+}
+
+impl<Stream: Read + Write + Debug> Connection<Stream> {
+    pub fn new(stream: Stream) -> Connection<Stream> {
+        Connection::with_config(stream, ParserConfig::default())
+    }
+
+    pub fn with_config(stream: Stream, config: ParserConfig) -> Connection<Stream> {
+        Connection {
+            stream: Some(AccReader::with_capacity(config.initial_buffer(), stream)),
+            parser: config,
+            current: None,
+            timeout: None,
+            set_timeout: None,
+            #[cfg(feature = "compression")]
+            compression: None,
+        }
+    }
+
+    /// compresses the responses according to the `Accept-Encoding` header
+    /// of their request
+    #[cfg(feature = "compression")]
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = Some(compression);
+    }
+
+    /// reads the next request. Returns `None` when the client closed the
+    /// connection between two requests, or after a response that closes it.
+    ///
+    /// A request refused with `HttpError::HeadersTooLarge` is answered with
+    /// `headers_too_large()` before returning the error
+    pub fn next_request(&mut self) -> Result<Option<http::Request<Body<Stream>>>, HttpError> {
+        if self.current.is_some() {
+            return Err(io::Error::other("the previous request was not answered").into());
+        }
+
+        let mut stream = match self.stream.take() {
+            Some(stream) => stream,
+            None => return Ok(None),
+        };
+
+        // the whole head must arrive before the deadline, each read waits
+        // for what is left of it
+        let deadline = self.timeout.map(|t| Instant::now() + t);
+        let set_timeout = self.set_timeout;
+        let before_read = |stream: &Stream| match (set_timeout, deadline) {
+            (Some(set_timeout), Some(deadline)) => {
+                let now = Instant::now();
+                if deadline <= now {
+                    return Err(io::Error::from(io::ErrorKind::TimedOut));
+                }
+                set_timeout(stream, Some(deadline - now))
+            }
+            _ => Ok(()),
+        };
+
+        if stream.buffer().is_empty() {
+            before_read(stream.get_ref())?;
+            match stream.fill_buf() {
+                Ok([]) => return Ok(None),
+                Ok(_) => {}
+                // idle connections are closed silently
+                Err(e) if self.set_timeout.is_some() && timeout::is_timeout(&e) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let (request, at_eof) = match parse_head(&mut stream, &self.parser, &before_read) {
+            Ok(head) => head,
+            Err(HttpError::HeadersTooLarge) => {
+                respond(stream.get_mut(), headers_too_large())?;
+                return Err(HttpError::HeadersTooLarge);
+            }
+            Err(e) => return Err(e),
+        };
+        let length = match body_length(&request) {
+            Ok(length) => length,
+            Err(e) => {
+                respond(stream.get_mut(), bad_request())?;
+                return Err(e);
+            }
+        };
+        if let (Some(set_timeout), Some(t)) = (self.set_timeout, self.timeout) {
+            set_timeout(stream.get_ref(), Some(t))?;
+        }
+        let mut request = request_with_body(stream, request, length, at_eof)?;
+
+        let handback = Arc::new(Mutex::new(None));
+        request.body_mut().handback = Some(handback.clone());
+        self.current = Some(Current {
+            method: request.method().clone(),
+            version: request.version(),
+            close: wants_close(request.version(), request.headers()),
+            handback,
+            #[cfg(feature = "compression")]
+            accept_encoding: request
+                .headers()
+                .get(http::header::ACCEPT_ENCODING)
+                .filter(|_| request.version() != http::Version::HTTP_10)
+                .cloned(),
+        });
+
+        Ok(Some(request))
+    }
+
+    /// answers the last request returned by `next_request`, and gives back
+    /// the response body. A `Connection` header is added if the client
+    /// cannot guess whether the connection stays open
+    pub fn respond<T: BufRead + Read + HasLength + Debug>(
+        &mut self,
+        mut response: http::Response<T>,
+    ) -> Result<T, HttpError> {
+        let current = match self.current.take() {
+            Some(current) => current,
+            None => return Err(io::Error::other("there is no request to answer").into()),
+        };
+
+        let rest = current.handback.lock().ok().and_then(|mut slot| slot.take());
+        let mut rest = match rest {
+            Some(rest) => rest,
+            None => {
+                error!("the request body was not dropped, closing the connection");
+                return Err(io::Error::other("the request body is still in use").into());
+            }
+        };
+
+        let connection = response.headers().get_all(http::header::CONNECTION);
+        let mut close = current.close || util::has_token(connection, b"close");
+        // skips what the handler left of the request body
+        if !close && io::copy(&mut rest, &mut io::sink()).is_err() {
+            close = true;
+        }
+
+        let headers = response.headers_mut();
+        if close && !util::has_token(headers.get_all(http::header::CONNECTION), b"close") {
+            headers.insert(http::header::CONNECTION, HeaderValue::from_static("close"));
+        } else if !close
+            && current.version == http::Version::HTTP_10
+            && !headers.contains_key(http::header::CONNECTION)
+        {
+            headers.insert(
+                http::header::CONNECTION,
+                HeaderValue::from_static("keep-alive"),
+            );
+        }
+
+        let mut stream = rest.into_inner();
+        #[cfg(feature = "compression")]
+        let body = match &self.compression {
+            Some(compression) => {
+                let response = compression.apply(current.accept_encoding.as_ref(), response);
+                let (_, body) = respond_to(stream.get_mut(), &current.method, response)?;
+                body.into_inner()
+            }
+            None => respond_to(stream.get_mut(), &current.method, response)?.1,
+        };
+        #[cfg(not(feature = "compression"))]
+        let (_, body) = respond_to(stream.get_mut(), &current.method, response)?;
+        if !close {
+            self.stream = Some(stream);
+        }
+        Ok(body)
+    }
+}
+
+impl<Stream: Read + Write + Debug + SetTimeout> Connection<Stream> {
+    /// like `Server::timeout`: bounds the wait for each request head, then
+    /// each read and write until it is answered. `None` by default
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+        self.set_timeout = Some(timeout::set_both);
+    }
+}
+
+// HTTP/1.0 connections are closed after each request unless asked otherwise
+fn wants_close(version: http::Version, headers: &http::HeaderMap) -> bool {
+    let connection = headers.get_all(http::header::CONNECTION);
+    if version == http::Version::HTTP_10 {
+        !util::has_token(connection, b"keep-alive")
+    } else {
+        util::has_token(connection, b"close")
+    }
+}
 
 pub fn parse<Stream: Read + Write + Debug>(
     stream: Stream,
 ) -> Result<http::Request<Body<Stream>>, HttpError> {
+    parse_with_config(stream, &ParserConfig::default())
+}
+
+/// like `parse`, with limits on the size of the request head. If they are
+/// exceeded, it returns `HttpError::HeadersTooLarge`, that can be answered
+/// with `headers_too_large()`
+pub fn parse_with_config<Stream: Read + Write + Debug>(
+    stream: Stream,
+    config: &ParserConfig,
+) -> Result<http::Request<Body<Stream>>, HttpError> {
+    let mut stream = AccReader::with_capacity(config.initial_buffer(), stream);
+    let (request, at_eof) = parse_head(&mut stream, config, &|_| Ok(()))?;
+    let length = body_length(&request)?;
+    request_with_body(stream, request, length, at_eof)
+}
+
+// parses the data already in the buffer before reading from the stream, so
+// a pipelined request can start in the buffer of the previous one
+fn parse_head<Stream: Read>(
+    stream: &mut AccReader<Stream>,
+    config: &ParserConfig,
+    // called before reading from the stream
+    before_read: &dyn Fn(&Stream) -> io::Result<()>,
+) -> Result<(http::request::Builder, bool), HttpError> {
     let mut request = http::Request::builder();
-    let mut stream = AccReader::with_capacity(16384, stream);
-    let mut at_eof;
+    let mut at_eof = false;
+    let mut header_count = config.initial_headers();
+    // false when parsing again with more headers, or parsing buffered data
+    let mut refill = stream.buffer().is_empty();
 
     loop {
         //println!("loop: bufferlen == {}", stream.buffer().len());
-        let data = stream.fill_buf()?;
-        at_eof = data.len() == 0;
+        if refill {
+            config.make_room(stream)?;
+            before_read(stream.get_ref())?;
+            // the buffer keeps the partial head from the previous iterations
+            let buffered = stream.buffer().len();
+            let data = stream.fill_buf()?;
+            at_eof = data.len() == buffered;
+        }
 
-        let mut headers = [httparse::EMPTY_HEADER; 30];
+        let mut headers = vec![httparse::EMPTY_HEADER; header_count];
         let mut req = httparse::Request::new(&mut headers);
 
         /*println!(
@@ -29,20 +560,30 @@ pub fn parse<Stream: Read + Write + Debug>(
             std::str::from_utf8(stream.buffer()).unwrap()
         );
         */
-        let status = req.parse(stream.buffer())?;
+        let status = match req.parse(stream.buffer()) {
+            Err(httparse::Error::TooManyHeaders) => {
+                header_count = config.more_headers(header_count)?;
+                refill = false;
+                continue;
+            }
+            // the method and the target were valid
+            Err(httparse::Error::Version) => return Err(HttpError::UnsupportedVersion),
+            status => status?,
+        };
+        refill = true;
         //println!("parser result: {:?}\n{:?}", status, req);
         if status.is_partial() {
             if at_eof {
-                panic!("got partial response and EOF");
+                return Err(HttpError::UnexpectedEof);
             } else {
                 continue;
             }
         }
 
-        let version = match req.version.unwrap() {
-            0 => http::Version::HTTP_10,
-            1 => http::Version::HTTP_11,
-            _ => panic!("invalid version number"),
+        let version = match req.version {
+            Some(0) => http::Version::HTTP_10,
+            Some(1) => http::Version::HTTP_11,
+            _ => return Err(HttpError::UnsupportedVersion),
         };
 
         let parsed_length = status.unwrap();
@@ -52,47 +593,89 @@ pub fn parse<Stream: Read + Write + Debug>(
             .version(version);
 
         for header in req.headers {
-            request = request.header(header.name, std::str::from_utf8(header.value).unwrap());
+            config.check_header(header)?;
+            request = request.header(header.name, util::header_value(header.value)?);
         }
 
         stream.consume(parsed_length);
-        break;
+        return Ok((request, at_eof));
     }
+}
 
+fn body_length(request: &http::request::Builder) -> Result<Length, HttpError> {
     let mut length = Length::None;
     if let Some(headers) = request.headers_ref() {
-        if let Some(v) = headers.get(http::header::CONTENT_LENGTH) {
-            if let Ok(nb) = v.to_str().unwrap().parse::<usize>() {
-                length = Length::ContentLength(nb);
+        // with pipelining, what is not read of an ambiguous body would be
+        // parsed as the next request
+        let content_length = util::content_length(headers)?;
+        let encodings = headers.get_all(http::header::TRANSFER_ENCODING);
+        if encodings.iter().next().is_some() {
+            if content_length.is_some() || !util::is_chunked(encodings) {
+                return Err(HttpError::InvalidBodyLength);
             }
-        }
-
-        if headers
-            .get_all(http::header::TRANSFER_ENCODING)
-            .iter()
-            .find(|c| util::eq_no_case(c.as_bytes(), "chunked".as_bytes()))
-            .is_some()
-        {
             length = Length::Chunked(0);
+        } else if let Some(nb) = content_length {
+            length = Length::ContentLength(nb);
         }
     }
+    Ok(length)
+}
+
+fn request_with_body<Stream: Read + Write + Debug>(
+    stream: AccReader<Stream>,
+    request: http::request::Builder,
+    length: Length,
+    at_eof: bool,
+) -> Result<http::Request<Body<Stream>>, HttpError> {
 
     //println!("finished parsing headers:\n{:?}", request);
-    let body = Body {
-        stream,
-        length,
-        at_eof,
-    };
+    let body = Body::new(stream, length, at_eof);
 
     Ok(request.body(body)?)
 }
 
+// answers requests refused with `HttpError::InvalidBodyLength`
+fn bad_request() -> http::Response<&'static [u8]> {
+    let mut response = http::Response::new(&b""[..]);
+    *response.status_mut() = http::StatusCode::BAD_REQUEST;
+    response.headers_mut().insert(
+        http::header::CONNECTION,
+        http::header::HeaderValue::from_static("close"),
+    );
+    response
+}
+
+/// `431 Request Header Fields Too Large` response, for requests refused with
+/// `HttpError::HeadersTooLarge`. The connection should be closed after it
+pub fn headers_too_large() -> http::Response<&'static [u8]> {
+    let mut response = http::Response::new(&b""[..]);
+    *response.status_mut() = http::StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE;
+    response.headers_mut().insert(
+        http::header::CONNECTION,
+        http::header::HeaderValue::from_static("close"),
+    );
+    response
+}
+
 pub fn respond<
     Stream: Read + Write + Debug,
     T: BufRead + Read + HasLength + Debug,
 >(
     stream: Stream,
     response: http::Response<T>,
+) -> Result<(Stream, T), HttpError> {
+    respond_to(stream, &http::Method::GET, response)
+}
+
+/// like `respond`, but takes into account the method of the request being
+/// answered: a response to HEAD gets its headers written, but not its body
+pub fn respond_to<
+    Stream: Read + Write + Debug,
+    T: BufRead + Read + HasLength + Debug,
+>(
+    stream: Stream,
+    method: &http::Method,
+    response: http::Response<T>,
 ) -> Result<(Stream, T), HttpError> {
     let mut stream = BufWriter::new(stream);
     //println!("sending response:\n{:?}", response);
@@ -116,7 +699,9 @@ pub fn respond<
     stream.write_all(&b"\r\n"[..])?;
 
     let mut body = response.into_body();
-    if has_length {
+    if *method == http::Method::HEAD {
+        // the body is given back unread
+    } else if has_length {
         std::io::copy(&mut body, &mut stream)?;
     } else {
         loop {
@@ -127,7 +712,7 @@ pub fn respond<
                     return Err(e.into());
                 }
                 Ok(data) => {
-                    if data.len() == 0 {
+                    if data.is_empty() {
                         //EOF
                         stream.write_all(&b"0\r\n\r\n"[..])?;
                         break;
@@ -148,10 +733,360 @@ pub fn respond<
     stream.flush()?;
     //println!("finished sending response");
 
-    let stream = match stream.into_inner() {
-        Ok(s) => s,
-        Err(_) => panic!(),
-    };
+    let stream = stream.into_inner().map_err(|e| e.into_error())?;
 
     Ok((stream, body))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read};
+
+    fn parse_bytes(input: &[u8]) -> Result<http::Request<Body<Cursor<Vec<u8>>>>, HttpError> {
+        parse(Cursor::new(input.to_vec()))
+    }
+
+    #[test]
+    fn truncated_head() {
+        for input in &[
+            &b""[..],
+            &b"GET"[..],
+            &b"GET / HTTP/1.1\r\nHost: exa"[..],
+            &b"GET / HTTP/1.1\r\nHost: example.com\r\n"[..],
+        ] {
+            match parse_bytes(input) {
+                Err(HttpError::UnexpectedEof) => {}
+                other => panic!("unexpected result for {:?}: {:?}", input, other),
+            }
+        }
+    }
+
+    #[test]
+    fn garbage() {
+        for input in &[
+            &b"\x00\x01\x02\r\n\r\n"[..],
+            &b"GET / HTTP/1.1\r\nHost example.com\r\n\r\n"[..],
+        ] {
+            match parse_bytes(input) {
+                Err(HttpError::Parser(_)) => {}
+                other => panic!("unexpected result for {:?}: {:?}", input, other),
+            }
+        }
+
+        match parse_bytes(b"GET / HTTP/2.0\r\n\r\n") {
+            Err(HttpError::UnsupportedVersion) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        match parse_bytes(b"GET / HTTP/1.1\r\nX-Name: caf\xe9\r\n\r\n") {
+            Err(HttpError::InvalidHeaderValue) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        for input in &[
+            &b"POST / HTTP/1.1\r\nContent-Length: abc\r\n\r\n"[..],
+            &b"POST / HTTP/1.1\r\nContent-Length: +4\r\n\r\n"[..],
+            &b"POST / HTTP/1.1\r\nContent-Length: 99999999999999999999999\r\n\r\n"[..],
+            &b"POST / HTTP/1.1\r\nContent-Length: 4, 5\r\n\r\n"[..],
+            &b"POST / HTTP/1.1\r\nContent-Length: 4\r\nContent-Length: 5\r\n\r\n"[..],
+            &b"POST / HTTP/1.1\r\nContent-Length: 4\r\nTransfer-Encoding: chunked\r\n\r\n"[..],
+            &b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked, gzip\r\n\r\n"[..],
+        ] {
+            match parse_bytes(input) {
+                Err(HttpError::InvalidBodyLength) => {}
+                other => panic!("unexpected result for {:?}: {:?}", input, other),
+            }
+        }
+        let req = parse_bytes(b"POST / HTTP/1.1\r\nContent-Length: 4, 4\r\n\r\ndata").unwrap();
+        assert_eq!(req.body().has_length(), Some(4));
+
+        // the body is not parsed as the next request, the connection is closed
+        let mut connection = Connection::new(Cursor::new(
+            b"POST / HTTP/1.1\r\nContent-Length: x\r\n\r\nGET /smuggled HTTP/1.1\r\n\r\n".to_vec(),
+        ));
+        match connection.next_request() {
+            Err(HttpError::InvalidBodyLength) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(connection.next_request().unwrap().is_none());
+    }
+
+    #[test]
+    fn header_limits() {
+        let mut input = b"GET / HTTP/1.1\r\n".to_vec();
+        for i in 0..40 {
+            input.extend_from_slice(format!("X-Header-{}: {}\r\n", i, i).as_bytes());
+        }
+        input.extend_from_slice(b"\r\n");
+
+        let req = parse_bytes(&input).unwrap();
+        assert_eq!(req.headers().len(), 40);
+
+        let config = ParserConfig {
+            max_headers: 35,
+            ..ParserConfig::default()
+        };
+        match parse_with_config(Cursor::new(input), &config) {
+            Err(HttpError::HeadersTooLarge) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let cookie = format!(
+            "GET / HTTP/1.1\r\nCookie: {}\r\n\r\n",
+            "a".repeat(20 * 1024)
+        );
+        let req = parse_with_config(
+            Cursor::new(cookie.clone().into_bytes()),
+            &ParserConfig {
+                max_header_size: 32 * 1024,
+                ..ParserConfig::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(req.headers()["cookie"].len(), 20 * 1024);
+
+        match parse_bytes(cookie.as_bytes()) {
+            Err(HttpError::HeadersTooLarge) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let config = ParserConfig {
+            max_head_size: 1024,
+            ..ParserConfig::default()
+        };
+        match parse_with_config(Cursor::new(cookie.into_bytes()), &config) {
+            Err(HttpError::HeadersTooLarge) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "tcp")]
+    #[test]
+    fn server_keep_alive() {
+        use crate::client::Client;
+        use crate::pool::{Pool, PoolKey};
+        use crate::resolver::TcpResolver;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://127.0.0.1:{}/hello", listener.local_addr().unwrap().port());
+        thread::spawn(move || {
+            Server::new().threads(2).serve(listener, |req| {
+                let body = format!("{} {}", req.method(), req.uri()).into_bytes();
+                http::Response::new(Cursor::new(body))
+            })
+        });
+
+        let pool = Pool::new();
+        let key = PoolKey::from_url(&url::Url::parse(&url).unwrap());
+        for body in &[&b""[..], &b"data"[..]] {
+            let mut res =
+                Client::<TcpStream, TcpResolver>::post_with_pool(&url, *body, &pool).unwrap();
+            let mut s = String::new();
+            res.body_mut().read_to_string(&mut s).unwrap();
+            assert_eq!(s, "POST /hello");
+            drop(res);
+            assert_eq!(pool.idle_count(&key), 1);
+        }
+    }
+
+    #[test]
+    fn panicking_handler() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            Server::new()
+                .threads(1)
+                .max_connections(1)
+                .serve(listener, |req| {
+                    if req.uri() == "/panic" {
+                        panic!("handler failure");
+                    }
+                    http::Response::new(Cursor::new(b"ok".to_vec()))
+                })
+        });
+
+        let get = |path: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream
+                .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+                .unwrap();
+            write!(
+                stream,
+                "GET {} HTTP/1.1\r\nConnection: close\r\n\r\n",
+                path
+            )
+            .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        for _ in 0..3 {
+            assert_eq!(get("/panic"), "");
+        }
+        assert!(get("/").ends_with("\r\n\r\nok"));
+    }
+
+    #[test]
+    fn slow_clients() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            Server::new()
+                .threads(1)
+                .timeout(Some(Duration::from_millis(200)))
+                .serve(listener, |_| http::Response::new(Cursor::new(b"ok".to_vec())))
+        });
+        let connect = || {
+            let stream = TcpStream::connect(addr).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            stream
+        };
+
+        // idle, then sending a byte at a time: both are closed
+        let mut idle = connect();
+        let mut trickle = connect();
+        for byte in b"GET / HTTP/1.1\r\n" {
+            if trickle.write_all(&[*byte]).is_err() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        let mut response = Vec::new();
+        assert_eq!(idle.read_to_end(&mut response).unwrap(), 0);
+        assert!(matches!(trickle.read_to_end(&mut response), Ok(0) | Err(_)));
+
+        let mut stream = connect();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.ends_with("\r\n\r\nok"));
+    }
+
+    #[cfg(all(feature = "tcp", feature = "compression"))]
+    #[test]
+    fn compressed_responses() {
+        use crate::client::Client;
+        use crate::encoding::Compression;
+        use crate::pool::{Pool, PoolKey};
+        use crate::resolver::TcpResolver;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://127.0.0.1:{}/", listener.local_addr().unwrap().port());
+        thread::spawn(move || {
+            Server::new()
+                .compression(Compression::new())
+                .serve(listener, |_| {
+                    http::Response::builder()
+                        .header("Content-Type", "text/plain")
+                        .body(Cursor::new("hello ".repeat(500)))
+                        .unwrap()
+                })
+        });
+
+        let pool = Pool::new();
+        let key = PoolKey::from_url(&url::Url::parse(&url).unwrap());
+        for accept_encoding in &["br", "gzip"] {
+            let mut client =
+                Client::<TcpStream, TcpResolver>::new_with_pool(&url, &pool).unwrap();
+            let req = http::Request::get(url.as_str())
+                .header("Accept-Encoding", *accept_encoding)
+                .body(&b""[..])
+                .unwrap();
+            let mut res = client.request(req).unwrap();
+            assert_eq!(res.headers()["content-encoding"], *accept_encoding);
+            assert_eq!(res.headers()["transfer-encoding"], "chunked");
+            assert_eq!(res.headers()["vary"], "accept-encoding");
+            io::copy(res.body_mut(), &mut io::sink()).unwrap();
+            drop(res);
+            assert_eq!(pool.idle_count(&key), 1);
+
+            let req = http::Request::get(url.as_str())
+                .header("Accept-Encoding", *accept_encoding)
+                .body(&b""[..])
+                .unwrap();
+            let mut res = client.request_decoded(req).unwrap();
+            let mut s = String::new();
+            res.body_mut().read_to_string(&mut s).unwrap();
+            assert_eq!(s, "hello ".repeat(500));
+        }
+    }
+
+    #[derive(Debug)]
+    struct Duplex {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Duplex {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Duplex {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn pipelining() {
+        let mut stream = Duplex {
+            input: Cursor::new(
+                b"GET /a HTTP/1.1\r\n\r\n\
+                  POST /b HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello\
+                  GET /c HTTP/1.0\r\nConnection: keep-alive\r\n\r\n\
+                  GET /d HTTP/1.0\r\n\r\n\
+                  GET /e HTTP/1.1\r\n\r\n"
+                    .to_vec(),
+            ),
+            output: Vec::new(),
+        };
+
+        // the body of /b is not read by the handler
+        Server::new()
+            .serve_connection(&mut stream, &|req: http::Request<_>| {
+                http::Response::new(Cursor::new(req.uri().path().as_bytes().to_vec()))
+            })
+            .unwrap();
+
+        let output = String::from_utf8(stream.output).unwrap();
+        assert_eq!(
+            output,
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n/a\
+             HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n/b\
+             HTTP/1.1 200 OK\r\nconnection: keep-alive\r\nContent-Length: 2\r\n\r\n/c\
+             HTTP/1.1 200 OK\r\nconnection: close\r\nContent-Length: 2\r\n\r\n/d"
+        );
+    }
+
+    #[test]
+    fn chunked_body() {
+        let mut req = parse_bytes(
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n",
+        )
+        .unwrap();
+        let mut s = String::new();
+        req.body_mut().read_to_string(&mut s).unwrap();
+        assert_eq!(s, "hello world");
+
+        let mut req = parse_bytes(
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhelloXX6\r\n world\r\n0\r\n\r\n",
+        )
+        .unwrap();
+        assert!(req.body_mut().read_to_string(&mut s).is_err());
+
+        let mut req =
+            parse_bytes(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n").unwrap();
+        assert!(req.body_mut().read_to_string(&mut s).is_err());
+    }
+}
@@ -1,12 +1,24 @@
 #[cfg(feature = "tls")]
-use rustls::{ClientConfig, ClientSession, StreamOwned};
+use rustls::{ClientConfig, ClientSession, ServerConfig, ServerSession, StreamOwned};
+use crate::error::TlsError;
+use crate::timeout::SetTimeout;
+#[cfg(feature = "tls")]
+use crate::tls::{self, TlsInfo};
+use crate::HttpError;
 use std::io::{self, Read, Write};
+#[cfg(feature = "tls")]
+use std::net::IpAddr;
+use std::net::{SocketAddr, TcpStream};
+#[cfg(feature = "tls")]
 use std::sync::Arc;
+use std::time::Duration;
 
 pub enum HttpStream<Stream: Read + Write> {
     Plain(Stream),
     #[cfg(feature = "tls")]
-    Tls(StreamOwned<ClientSession, Stream>),
+    Tls(Box<StreamOwned<ClientSession, Stream>>),
+    #[cfg(feature = "tls")]
+    TlsServer(Box<StreamOwned<ServerSession, Stream>>),
 }
 
 impl<Stream: Read + Write> HttpStream<Stream> {
@@ -14,21 +26,73 @@ impl<Stream: Read + Write> HttpStream<Stream> {
         HttpStream::Plain(stream)
     }
 
+    /// client side of a TLS connection, trusting the `webpki-roots`
+    /// certificates. `host` is a DNS name or an IP address
+    #[cfg(feature = "tls")]
+    pub fn tls(stream: Stream, host: &str) -> Result<HttpStream<Stream>, HttpError> {
+        HttpStream::tls_with_config(stream, host, &tls::default_client_config())
+    }
+
+    /// like `tls`, with a configuration built by `tls::TlsConfig`
+    #[cfg(feature = "tls")]
+    pub fn tls_with_config(
+        stream: Stream,
+        host: &str,
+        config: &Arc<ClientConfig>,
+    ) -> Result<HttpStream<Stream>, HttpError> {
+        let invalid = || TlsError::InvalidServerName(host.to_string());
+        let ip = host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>();
+
+        let sess = match ip {
+            Ok(ip) => {
+                let name = tls::ip_server_name(ip);
+                let dns_name = webpki::DNSNameRef::try_from_ascii_str(&name).map_err(|_| invalid())?;
+                ClientSession::new(&tls::ip_client_config(config, ip), dns_name)
+            }
+            Err(_) => {
+                let dns_name = webpki::DNSNameRef::try_from_ascii_str(host).map_err(|_| invalid())?;
+                ClientSession::new(config, dns_name)
+            }
+        };
+        Ok(HttpStream::Tls(Box::new(StreamOwned::new(sess, stream))))
+    }
+
+    /// server side of a TLS connection. The handshake happens on the first
+    /// read or write
+    #[cfg(feature = "tls")]
+    pub fn tls_server(stream: Stream, config: &Arc<ServerConfig>) -> HttpStream<Stream> {
+        let sess = ServerSession::new(config);
+        HttpStream::TlsServer(Box::new(StreamOwned::new(sess, stream)))
+    }
+
+    /// the underlying stream, below TLS
+    pub fn get_ref(&self) -> &Stream {
+        match self {
+            HttpStream::Plain(s) => s,
+            #[cfg(feature = "tls")]
+            HttpStream::Tls(s) => &s.sock,
+            #[cfg(feature = "tls")]
+            HttpStream::TlsServer(s) => &s.sock,
+        }
+    }
+
+    /// protocol version, cipher suite, ALPN protocol and certificates of
+    /// the peer. `None` for plaintext streams, or before the handshake
     #[cfg(feature = "tls")]
-    pub fn tls(stream: Stream, host: &str) -> HttpStream<Stream> {
-        let mut config = ClientConfig::new();
-        config
-            .root_store
-            .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
-
-        let dns_name = webpki::DNSNameRef::try_from_ascii_str(host).unwrap();
-        let sess = ClientSession::new(&Arc::new(config), dns_name);
-        HttpStream::Tls(StreamOwned::new(sess, stream))
+    pub fn tls_info(&self) -> Option<TlsInfo> {
+        match self {
+            HttpStream::Plain(_) => None,
+            HttpStream::Tls(s) => TlsInfo::from_session(&s.sess),
+            HttpStream::TlsServer(s) => TlsInfo::from_session(&s.sess),
+        }
     }
 
     #[cfg(not(feature = "tls"))]
-    pub fn tls(stream: Stream, host: &str) -> HttpStream<Stream> {
-        unimplemented!()
+    pub fn tls(_stream: Stream, _host: &str) -> Result<HttpStream<Stream>, HttpError> {
+        Err(TlsError::Config("TLS support not compiled in".to_string()).into())
     }
 }
 
@@ -37,7 +101,9 @@ impl<Stream: Read + Write> Read for HttpStream<Stream> {
         match self {
             HttpStream::Plain(s) => s.read(buf),
             #[cfg(feature = "tls")]
-            HttpStream::Tls(s) => s.read(buf),
+            HttpStream::Tls(s) => tls::client_io(s, |s| s.read(buf)),
+            #[cfg(feature = "tls")]
+            HttpStream::TlsServer(s) => s.read(buf),
         }
     }
 }
@@ -47,7 +113,9 @@ impl<Stream: Read + Write> Write for HttpStream<Stream> {
         match self {
             HttpStream::Plain(s) => s.write(buf),
             #[cfg(feature = "tls")]
-            HttpStream::Tls(s) => s.write(buf),
+            HttpStream::Tls(s) => tls::client_io(s, |s| s.write(buf)),
+            #[cfg(feature = "tls")]
+            HttpStream::TlsServer(s) => s.write(buf),
         }
     }
 
@@ -55,11 +123,63 @@ impl<Stream: Read + Write> Write for HttpStream<Stream> {
         match self {
             HttpStream::Plain(s) => s.flush(),
             #[cfg(feature = "tls")]
-            HttpStream::Tls(s) => s.flush(),
+            HttpStream::Tls(s) => tls::client_io(s, |s| s.flush()),
+            #[cfg(feature = "tls")]
+            HttpStream::TlsServer(s) => s.flush(),
         }
     }
 }
 
+impl<Stream: Read + Write + SetTimeout> SetTimeout for HttpStream<Stream> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            HttpStream::Plain(s) => s.set_read_timeout(timeout),
+            #[cfg(feature = "tls")]
+            HttpStream::Tls(s) => s.sock.set_read_timeout(timeout),
+            #[cfg(feature = "tls")]
+            HttpStream::TlsServer(s) => s.sock.set_read_timeout(timeout),
+        }
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            HttpStream::Plain(s) => s.set_write_timeout(timeout),
+            #[cfg(feature = "tls")]
+            HttpStream::Tls(s) => s.sock.set_write_timeout(timeout),
+            #[cfg(feature = "tls")]
+            HttpStream::TlsServer(s) => s.sock.set_write_timeout(timeout),
+        }
+    }
+}
+
+/// streams connected to an IP address
+pub trait PeerAddr {
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+}
+
+impl PeerAddr for TcpStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+}
+
+impl<Stream: Read + Write + PeerAddr> PeerAddr for HttpStream<Stream> {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            HttpStream::Plain(s) => s.peer_addr(),
+            #[cfg(feature = "tls")]
+            HttpStream::Tls(s) => s.sock.peer_addr(),
+            #[cfg(feature = "tls")]
+            HttpStream::TlsServer(s) => s.sock.peer_addr(),
+        }
+    }
+}
+
+/// address of the server that sent a response, stored in its extensions
+/// by clients that called `Client::record_remote_addr`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RemoteAddr(pub SocketAddr);
+
 impl<Stream: Read + Write> std::fmt::Debug for HttpStream<Stream> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
         f.debug_struct("HttpStream").finish()
@@ -0,0 +1,138 @@
+//! timeouts for the client and the server
+//!
+//! socket timeouts are set through the `SetTimeout` trait, so they are only
+//! available for streams that implement it
+
+use crate::error::TimeoutPhase;
+use std::io;
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+/// streams that can limit how long a read or write blocks
+pub trait SetTimeout {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl SetTimeout for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl SetTimeout for std::os::unix::net::UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        std::os::unix::net::UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        std::os::unix::net::UnixStream::set_write_timeout(self, timeout)
+    }
+}
+
+// `SetTimeout` for both directions, kept by types that do not require the
+// trait from their stream
+pub(crate) type SetTimeoutFn<Stream> = fn(&Stream, Option<Duration>) -> io::Result<()>;
+
+/// every timeout is optional, `None` means waiting forever
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timeouts {
+    /// given to the resolver when opening a connection
+    pub connect: Option<Duration>,
+    /// from the end of the request to the end of the response headers
+    pub first_byte: Option<Duration>,
+    /// longest wait between two reads of the response body
+    pub body_read: Option<Duration>,
+    /// from the start of `Client::request` to the end of the response
+    /// body, including redirects
+    pub total: Option<Duration>,
+}
+
+impl Timeouts {
+    /// the timeout to apply to a phase, bounded by the time left until
+    /// `deadline`. Also returns the phase to blame if it expires
+    pub(crate) fn phase(
+        &self,
+        phase: TimeoutPhase,
+        deadline: Option<Instant>,
+    ) -> Result<(Option<Duration>, TimeoutPhase), TimeoutPhase> {
+        let timeout = match phase {
+            TimeoutPhase::Connect => self.connect,
+            TimeoutPhase::FirstByte => self.first_byte,
+            TimeoutPhase::BodyRead => self.body_read,
+            TimeoutPhase::Total => None,
+        };
+
+        let remaining = match deadline {
+            None => return Ok((timeout, phase)),
+            Some(deadline) => {
+                let now = Instant::now();
+                // a zero duration is rejected by the socket
+                if deadline <= now {
+                    return Err(TimeoutPhase::Total);
+                }
+                deadline - now
+            }
+        };
+
+        Ok(match timeout {
+            Some(t) if t < remaining => (Some(t), phase),
+            _ => (Some(remaining), TimeoutPhase::Total),
+        })
+    }
+}
+
+// read and write timeouts at once, as a `SetTimeoutFn`
+pub(crate) fn set_both<S: SetTimeout>(stream: &S, timeout: Option<Duration>) -> io::Result<()> {
+    stream.set_read_timeout(timeout)?;
+    stream.set_write_timeout(timeout)
+}
+
+/// true if the error comes from a socket timeout
+pub(crate) fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deadline_bounds_phases() {
+        let timeouts = Timeouts {
+            first_byte: Some(Duration::from_secs(1)),
+            ..Timeouts::default()
+        };
+
+        assert_eq!(
+            timeouts.phase(TimeoutPhase::FirstByte, None),
+            Ok((Some(Duration::from_secs(1)), TimeoutPhase::FirstByte))
+        );
+        assert_eq!(
+            timeouts.phase(TimeoutPhase::BodyRead, None),
+            Ok((None, TimeoutPhase::BodyRead))
+        );
+
+        let (t, phase) = timeouts
+            .phase(
+                TimeoutPhase::FirstByte,
+                Some(Instant::now() + Duration::from_millis(500)),
+            )
+            .unwrap();
+        assert!(t.unwrap() <= Duration::from_millis(500));
+        assert_eq!(phase, TimeoutPhase::Total);
+
+        assert_eq!(
+            timeouts.phase(TimeoutPhase::FirstByte, Some(Instant::now())),
+            Err(TimeoutPhase::Total)
+        );
+    }
+}
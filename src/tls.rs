@@ -0,0 +1,875 @@
+//! TLS configuration
+//!
+//! `TlsConfig` builds the rustls configuration of the client, used by
+//! `HttpStream::tls_with_config` and `Client::set_tls_config`.
+//! `TlsServerConfig` loads the certificates of an HTTPS server from PEM
+//! files. Its `build` method gives the rustls configuration expected by
+//! `HttpStream::tls_server` and `server::TlsListener`
+
+use crate::error::TlsError;
+use crate::x509;
+use crate::HttpError;
+use log::warn;
+use ring::digest;
+use rustls::internal::pemfile;
+use rustls::sign::{self, CertifiedKey};
+use rustls::{
+    AllowAnyAuthenticatedClient, Certificate, CipherSuite, ClientConfig, ClientHello,
+    ClientSession, NoClientAuth, PrivateKey, ProtocolVersion, ResolvesServerCert, RootCertStore,
+    ServerCertVerified, ServerCertVerifier, ServerConfig, Session, StreamOwned, TLSError,
+    WebPKIVerifier,
+};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError, Weak};
+
+/// client TLS settings.
+///
+/// The configuration returned by `build` should be created once and shared
+/// between connections: it keeps the TLS sessions, so that connecting again
+/// to a server resumes the previous session
+#[derive(Clone)]
+pub struct TlsConfig {
+    roots: RootCertStore,
+    webpki_roots: bool,
+    client_certificate: Option<(Vec<Certificate>, PrivateKey)>,
+    alpn_protocols: Vec<Vec<u8>>,
+    verifier: Verifier,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig {
+            roots: RootCertStore::empty(),
+            webpki_roots: true,
+            client_certificate: None,
+            alpn_protocols: Vec::new(),
+            verifier: Verifier::default(),
+        }
+    }
+}
+
+impl TlsConfig {
+    /// trusts the Mozilla root certificates from `webpki-roots`
+    pub fn new() -> TlsConfig {
+        TlsConfig::default()
+    }
+
+    /// trusts the certificates of a PEM file, in addition to the
+    /// `webpki-roots` ones unless they are disabled
+    pub fn add_root_certificates<P: AsRef<Path>>(mut self, pem: P) -> Result<TlsConfig, HttpError> {
+        for cert in load_certs(pem.as_ref())? {
+            self.roots.add(&cert).map_err(|e| {
+                config_error(format!(
+                    "invalid root certificate in {:?}: {:?}",
+                    pem.as_ref(),
+                    e
+                ))
+            })?;
+        }
+        Ok(self)
+    }
+
+    /// with `false`, only the certificates given to `add_root_certificates`
+    /// are trusted
+    pub fn webpki_roots(mut self, enabled: bool) -> TlsConfig {
+        self.webpki_roots = enabled;
+        self
+    }
+
+    /// certificate chain and key sent to servers asking for a client
+    /// certificate
+    pub fn client_certificate<P: AsRef<Path>, Q: AsRef<Path>>(
+        mut self,
+        cert: P,
+        key: Q,
+    ) -> Result<TlsConfig, HttpError> {
+        let certs = load_certs(cert.as_ref())?;
+        let key = load_key(key.as_ref())?;
+        self.client_certificate = Some((certs, key));
+        Ok(self)
+    }
+
+    /// protocols offered with ALPN, in order of preference, like `b"http/1.1"`
+    pub fn alpn_protocols(mut self, protocols: &[&[u8]]) -> TlsConfig {
+        self.alpn_protocols = protocols.iter().map(|p| p.to_vec()).collect();
+        self
+    }
+
+    /// accepts only servers whose public key has this SHA-256 hash, on top
+    /// of the usual verification. With multiple pins, any of them can match.
+    /// A mismatch fails with `TlsError::PinMismatch`.
+    ///
+    /// The hash of a certificate's key is given by `spki_sha256`
+    pub fn pin_sha256(mut self, hash: [u8; 32]) -> TlsConfig {
+        self.verifier.pins.push(hash);
+        self
+    }
+
+    /// DANGEROUS: accepts any certificate, for any name, without checking
+    /// its validity. Pins are still checked. Only meant for tests against
+    /// local servers with throwaway certificates
+    pub fn dangerous_accept_any_certificate(mut self) -> TlsConfig {
+        self.verifier.accept_any = true;
+        self
+    }
+
+    pub fn build(self) -> Result<Arc<ClientConfig>, HttpError> {
+        if self.verifier.accept_any {
+            warn!("TLS certificate verification is disabled");
+        }
+
+        let mut config = ClientConfig::new();
+        config.root_store = self.roots;
+        if self.webpki_roots {
+            config
+                .root_store
+                .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+        }
+        if let Some((certs, key)) = self.client_certificate {
+            config
+                .set_single_client_cert(certs, key)
+                .map_err(|e| config_error(format!("invalid client certificate: {}", e)))?;
+        }
+        config.set_protocols(&self.alpn_protocols);
+        Ok(with_verifier(config, self.verifier))
+    }
+}
+
+impl std::fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        f.debug_struct("TlsConfig")
+            .field("roots", &self.roots.len())
+            .field("webpki_roots", &self.webpki_roots)
+            .field("client_certificate", &self.client_certificate.is_some())
+            .field("alpn_protocols", &self.alpn_protocols)
+            .field("pins", &self.verifier.pins.len())
+            .field("accept_any", &self.verifier.accept_any)
+            .finish()
+    }
+}
+
+/// details of an established TLS connection, from `HttpStream::tls_info`.
+///
+/// The client adds it to the extensions of responses received over TLS
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsInfo {
+    pub protocol_version: Option<ProtocolVersion>,
+    pub cipher_suite: Option<CipherSuite>,
+    /// protocol selected with ALPN
+    pub alpn_protocol: Option<Vec<u8>>,
+    /// DER certificates sent by the peer, starting with its own
+    pub peer_certificates: Vec<Vec<u8>>,
+}
+
+impl TlsInfo {
+    /// `None` while the handshake is not finished
+    pub(crate) fn from_session<S: Session>(session: &S) -> Option<TlsInfo> {
+        if session.is_handshaking() {
+            return None;
+        }
+
+        Some(TlsInfo {
+            protocol_version: session.get_protocol_version(),
+            cipher_suite: session.get_negotiated_ciphersuite().map(|c| c.suite),
+            alpn_protocol: session.get_alpn_protocol().map(|p| p.to_vec()),
+            peer_certificates: session
+                .get_peer_certificates()
+                .unwrap_or_default()
+                .into_iter()
+                .map(|c| c.0)
+                .collect(),
+        })
+    }
+}
+
+/// configuration used when none is given, shared by all connections
+pub(crate) fn default_client_config() -> Arc<ClientConfig> {
+    static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+    CONFIG
+        .get_or_init(|| {
+            let mut config = ClientConfig::new();
+            config
+                .root_store
+                .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+            with_verifier(config, Verifier::default())
+        })
+        .clone()
+}
+
+// rustls does not give back the verifier of a configuration: the ones of
+// the configurations built here are kept aside, to be copied with the
+// address expected from the certificate for connections to an IP address
+static VERIFIERS: Mutex<Vec<(Weak<ClientConfig>, Verifier)>> = Mutex::new(Vec::new());
+
+fn with_verifier(mut config: ClientConfig, verifier: Verifier) -> Arc<ClientConfig> {
+    config
+        .dangerous()
+        .set_certificate_verifier(Arc::new(verifier.clone()));
+    let config = Arc::new(config);
+
+    let mut verifiers = VERIFIERS.lock().unwrap_or_else(PoisonError::into_inner);
+    verifiers.retain(|(config, _)| config.strong_count() > 0);
+    verifiers.push((Arc::downgrade(&config), verifier));
+    config
+}
+
+/// configuration for a connection to an IP address, without SNI. Its
+/// verifier checks the address against the certificate, if `config` was
+/// built by `TlsConfig`: other verifiers only see the name given by
+/// `ip_server_name`
+pub(crate) fn ip_client_config(config: &Arc<ClientConfig>, ip: IpAddr) -> Arc<ClientConfig> {
+    let verifier = VERIFIERS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+        .find(|(c, _)| c.as_ptr() == Arc::as_ptr(config))
+        .map(|(_, verifier)| verifier.clone());
+
+    let mut config = (**config).clone();
+    config.enable_sni = false;
+    if let Some(verifier) = verifier {
+        config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(Verifier {
+                ip: Some(ip),
+                ..verifier
+            }));
+    }
+    Arc::new(config)
+}
+
+// rustls only knows DNS names, and keeps the TLS sessions by name: each IP
+// address gets a name of its own, that no certificate can be valid for
+pub(crate) fn ip_server_name(ip: IpAddr) -> String {
+    let labels: Vec<String> = match ip {
+        IpAddr::V4(ip) => ip.octets().iter().map(|o| o.to_string()).collect(),
+        IpAddr::V6(ip) => ip.segments().iter().map(|s| format!("{:x}", s)).collect(),
+    };
+    format!("{}.ip.invalid", labels.join("-"))
+}
+
+/// SHA-256 hash of the subjectPublicKeyInfo of a DER certificate, the
+/// value expected by `TlsConfig::pin_sha256`
+pub fn spki_sha256(cert: &[u8]) -> Option<[u8; 32]> {
+    let spki = x509::spki(cert)?;
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(digest::digest(&digest::SHA256, spki).as_ref());
+    Some(hash)
+}
+
+thread_local! {
+    // hash of the key that matched no pin. rustls calls the verifier
+    // during the read or write that receives the certificate, so it is
+    // picked up on the same thread by `client_io`
+    static PIN_MISMATCH: Cell<Option<[u8; 32]>> = const { Cell::new(None) };
+}
+
+// carries the typed error of a failed handshake through the stream, until
+// it becomes an `HttpError`
+#[derive(Debug)]
+struct HandshakeError(TlsError);
+
+impl std::fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "TLS handshake failed: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for HandshakeError {}
+
+/// reads or writes on the client side of a TLS connection. The rustls
+/// errors of the handshake are replaced by a `TlsError`, given back by
+/// `handshake_error`
+pub(crate) fn client_io<S: Read + Write, T>(
+    stream: &mut StreamOwned<ClientSession, S>,
+    op: impl FnOnce(&mut StreamOwned<ClientSession, S>) -> io::Result<T>,
+) -> io::Result<T> {
+    if !stream.sess.is_handshaking() {
+        return op(stream);
+    }
+
+    PIN_MISMATCH.with(|hash| hash.set(None));
+    op(stream).map_err(|e| {
+        let e = match e.get_ref().and_then(|e| e.downcast_ref::<TLSError>()) {
+            Some(e) => e,
+            None => return e,
+        };
+        let tls = match PIN_MISMATCH.with(Cell::take) {
+            Some(presented) => TlsError::PinMismatch { presented },
+            None => TlsError::Handshake(e.to_string()),
+        };
+        io::Error::new(io::ErrorKind::InvalidData, HandshakeError(tls))
+    })
+}
+
+/// the `TlsError` of a handshake that failed in `client_io`
+pub(crate) fn handshake_error(e: &io::Error) -> Option<TlsError> {
+    let e = e.get_ref()?.downcast_ref::<HandshakeError>()?;
+    Some(e.0.clone())
+}
+
+/// verifies certificates like rustls does, or the IP address of the
+/// certificate for a server reached by IP. Then checks the pins
+#[derive(Debug, Clone, Default)]
+struct Verifier {
+    pins: Vec<[u8; 32]>,
+    accept_any: bool,
+    // set in the copies made by `ip_client_config`
+    ip: Option<IpAddr>,
+}
+
+impl Verifier {
+    fn verify_name(
+        &self,
+        roots: &RootCertStore,
+        presented_certs: &[Certificate],
+        dns_name: webpki::DNSNameRef,
+        ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        let ip = match self.ip {
+            Some(ip) => ip,
+            None => {
+                return WebPKIVerifier::new().verify_server_cert(
+                    roots,
+                    presented_certs,
+                    dns_name,
+                    ocsp_response,
+                )
+            }
+        };
+
+        verify_chain(roots, presented_certs)?;
+        if x509::ip_addresses(&presented_certs[0].0).contains(&ip) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(TLSError::WebPKIError(webpki::Error::CertNotValidForName))
+        }
+    }
+}
+
+impl ServerCertVerifier for Verifier {
+    fn verify_server_cert(
+        &self,
+        roots: &RootCertStore,
+        presented_certs: &[Certificate],
+        dns_name: webpki::DNSNameRef,
+        ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        if !self.accept_any {
+            self.verify_name(roots, presented_certs, dns_name, ocsp_response)?;
+        }
+
+        if !self.pins.is_empty() {
+            let end_entity = presented_certs
+                .first()
+                .ok_or(TLSError::NoCertificatesPresented)?;
+            let hash =
+                spki_sha256(&end_entity.0).ok_or(TLSError::WebPKIError(webpki::Error::BadDER))?;
+            if !self.pins.contains(&hash) {
+                PIN_MISMATCH.with(|presented| presented.set(Some(hash)));
+                return Err(TLSError::General("public key pin mismatch".to_string()));
+            }
+        }
+
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+static SUPPORTED_SIG_ALGS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ECDSA_P384_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::ED25519,
+    &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+    &webpki::RSA_PKCS1_3072_8192_SHA384,
+];
+
+// the chain must lead to one of the roots, without checking the name
+fn verify_chain(roots: &RootCertStore, presented_certs: &[Certificate]) -> Result<(), TLSError> {
+    let (end_entity, intermediates) = presented_certs
+        .split_first()
+        .ok_or(TLSError::NoCertificatesPresented)?;
+    let cert = webpki::EndEntityCert::from(&end_entity.0).map_err(TLSError::WebPKIError)?;
+    let chain: Vec<&[u8]> = intermediates.iter().map(|c| c.0.as_ref()).collect();
+    let anchors: Vec<webpki::TrustAnchor> =
+        roots.roots.iter().map(|r| r.to_trust_anchor()).collect();
+    let now = webpki::Time::try_from(std::time::SystemTime::now())
+        .map_err(|_| TLSError::FailedToGetCurrentTime)?;
+
+    cert.verify_is_valid_tls_server_cert(
+        SUPPORTED_SIG_ALGS,
+        &webpki::TLSServerTrustAnchors(&anchors),
+        &chain,
+        now,
+    )
+    .map_err(TLSError::WebPKIError)
+}
+
+/// certificates of an HTTPS server. The one sent to a client is chosen
+/// with the name it asked for with SNI, or is the default one
+#[derive(Clone, Default)]
+pub struct TlsServerConfig {
+    default: Option<CertifiedKey>,
+    by_name: HashMap<String, CertifiedKey>,
+    client_roots: Option<RootCertStore>,
+}
+
+impl TlsServerConfig {
+    pub fn new() -> TlsServerConfig {
+        TlsServerConfig::default()
+    }
+
+    /// certificate sent when the client did not use SNI, or asked for a
+    /// name without a certificate. `cert` can contain the whole chain
+    pub fn default_certificate<P: AsRef<Path>, Q: AsRef<Path>>(
+        mut self,
+        cert: P,
+        key: Q,
+    ) -> Result<TlsServerConfig, HttpError> {
+        self.default = Some(load_certified_key(cert.as_ref(), key.as_ref())?);
+        Ok(self)
+    }
+
+    /// certificate sent to clients asking for `name`. Fails if it is not
+    /// valid for that name
+    pub fn sni_certificate<P: AsRef<Path>, Q: AsRef<Path>>(
+        mut self,
+        name: &str,
+        cert: P,
+        key: Q,
+    ) -> Result<TlsServerConfig, HttpError> {
+        let certified = load_certified_key(cert.as_ref(), key.as_ref())?;
+        let dns_name = webpki::DNSNameRef::try_from_ascii_str(name)
+            .map_err(|_| TlsError::InvalidServerName(name.to_string()))?;
+        certified
+            .cross_check_end_entity_cert(Some(dns_name))
+            .map_err(|e| config_error(format!("certificate for {}: {}", name, e)))?;
+
+        self.by_name.insert(name.to_ascii_lowercase(), certified);
+        Ok(self)
+    }
+
+    /// requires clients to send a certificate signed by one of the
+    /// certificates of a PEM file
+    pub fn client_auth_roots<P: AsRef<Path>>(
+        mut self,
+        pem: P,
+    ) -> Result<TlsServerConfig, HttpError> {
+        let roots = self.client_roots.get_or_insert_with(RootCertStore::empty);
+        for cert in load_certs(pem.as_ref())? {
+            roots.add(&cert).map_err(|e| {
+                config_error(format!(
+                    "invalid root certificate in {:?}: {:?}",
+                    pem.as_ref(),
+                    e
+                ))
+            })?;
+        }
+        Ok(self)
+    }
+
+    /// rustls configuration advertising `http/1.1` with ALPN
+    pub fn build(mut self) -> Arc<ServerConfig> {
+        let verifier = match self.client_roots.take() {
+            Some(roots) => AllowAnyAuthenticatedClient::new(roots),
+            None => NoClientAuth::new(),
+        };
+        let mut config = ServerConfig::new(verifier);
+        config.cert_resolver = Arc::new(self);
+        config.set_protocols(&[b"http/1.1".to_vec()]);
+        Arc::new(config)
+    }
+}
+
+impl ResolvesServerCert for TlsServerConfig {
+    fn resolve(&self, client_hello: ClientHello) -> Option<CertifiedKey> {
+        let by_name = client_hello.server_name().and_then(|name| {
+            let name: &str = name.into();
+            self.by_name.get(&name.to_ascii_lowercase())
+        });
+
+        by_name.or(self.default.as_ref()).cloned()
+    }
+}
+
+impl std::fmt::Debug for TlsServerConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        f.debug_struct("TlsServerConfig")
+            .field("default", &self.default.is_some())
+            .field("names", &self.by_name.keys().collect::<Vec<_>>())
+            .field("client_auth", &self.client_roots.is_some())
+            .finish()
+    }
+}
+
+fn config_error(message: String) -> HttpError {
+    TlsError::Config(message).into()
+}
+
+fn load_certs(path: &Path) -> Result<Vec<Certificate>, HttpError> {
+    let certs = pemfile::certs(&mut &std::fs::read(path)?[..])
+        .map_err(|_| config_error(format!("invalid PEM file: {:?}", path)))?;
+    if certs.is_empty() {
+        return Err(config_error(format!("no certificate in {:?}", path)));
+    }
+    Ok(certs)
+}
+
+// the key can be in PKCS#8 or in the older RSA format
+fn load_key(path: &Path) -> Result<PrivateKey, HttpError> {
+    let pem = std::fs::read(path)?;
+    let mut keys = pemfile::pkcs8_private_keys(&mut &pem[..])
+        .map_err(|_| config_error(format!("invalid PEM file: {:?}", path)))?;
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut &pem[..])
+            .map_err(|_| config_error(format!("invalid PEM file: {:?}", path)))?;
+    }
+
+    if keys.is_empty() {
+        Err(config_error(format!("no private key in {:?}", path)))
+    } else {
+        Ok(keys.swap_remove(0))
+    }
+}
+
+fn load_certified_key(cert: &Path, key: &Path) -> Result<CertifiedKey, HttpError> {
+    let certs = load_certs(cert)?;
+    let signing_key = sign::any_supported_type(&load_key(key)?)
+        .map_err(|_| config_error(format!("unsupported private key in {:?}", key)))?;
+
+    Ok(CertifiedKey::new(certs, Arc::new(signing_key)))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::server::{Server, TlsListener};
+    use crate::stream::HttpStream;
+    use rustls::{ClientConfig, ClientSession, StreamOwned};
+    use std::io::{Cursor, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::path::PathBuf;
+
+    // writes a self-signed certificate and its key, returns the paths and
+    // the DER certificate
+    pub(crate) fn self_signed(test: &str, name: &str) -> (PathBuf, PathBuf, Vec<u8>) {
+        let cert = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+        write_cert(test, name, cert)
+    }
+
+    fn write_cert(test: &str, name: &str, cert: rcgen::Certificate) -> (PathBuf, PathBuf, Vec<u8>) {
+        let dir = std::env::temp_dir();
+        let prefix = format!("ghc-{}-{}-{}", test, std::process::id(), name);
+        let cert_path = dir.join(format!("{}.crt", prefix));
+        let key_path = dir.join(format!("{}.key", prefix));
+        std::fs::write(&cert_path, cert.serialize_pem().unwrap()).unwrap();
+        std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+        (cert_path, key_path, cert.serialize_der().unwrap())
+    }
+
+    #[test]
+    fn https_server() {
+        let (default_cert, default_key, _) = self_signed("server", "default.test");
+        let (cert, key, der) = self_signed("server", "localhost");
+        let config = TlsServerConfig::new()
+            .default_certificate(&default_cert, &default_key)
+            .unwrap()
+            .sni_certificate("localhost", &cert, &key)
+            .unwrap();
+        assert!(TlsServerConfig::new()
+            .sni_certificate("example.com", &cert, &key)
+            .is_err());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let listener = TlsListener::new(listener, config.build());
+        std::thread::spawn(move || {
+            Server::new().threads(1).serve(listener, |req| {
+                http::Response::new(Cursor::new(req.uri().path().as_bytes().to_vec()))
+            })
+        });
+
+        // only the certificate for localhost is trusted, the default one
+        // would be refused
+        let mut client_config = ClientConfig::new();
+        client_config
+            .root_store
+            .add(&rustls::Certificate(der))
+            .unwrap();
+        client_config.set_protocols(&[b"http/1.1".to_vec()]);
+        let dns_name = webpki::DNSNameRef::try_from_ascii_str("localhost").unwrap();
+        let session = ClientSession::new(&Arc::new(client_config), dns_name);
+        let mut stream = StreamOwned::new(session, TcpStream::connect(addr).unwrap());
+
+        stream
+            .write_all(b"GET /secure HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let expected = "HTTP/1.1 200 OK\r\nContent-Length: 7\r\n\r\n/secure";
+        let mut response = vec![0; expected.len()];
+        stream.read_exact(&mut response).unwrap();
+        assert_eq!(String::from_utf8(response).unwrap(), expected);
+        assert_eq!(stream.sess.get_alpn_protocol(), Some(&b"http/1.1"[..]));
+
+        for path in &[default_cert, default_key, cert, key] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[cfg(feature = "tcp")]
+    #[test]
+    fn client_certificate() {
+        use crate::client::Client;
+        use crate::resolver::TcpResolver;
+
+        let (cert, key, _) = self_signed("mtls", "localhost");
+        let (client_cert, client_key, _) = self_signed("mtls", "client.test");
+        let config = TlsServerConfig::new()
+            .default_certificate(&cert, &key)
+            .unwrap()
+            .client_auth_roots(&client_cert)
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "https://localhost:{}/",
+            listener.local_addr().unwrap().port()
+        );
+        let listener = TlsListener::new(listener, config.build());
+        std::thread::spawn(move || {
+            Server::new().threads(1).serve(listener, |_| {
+                http::Response::new(Cursor::new(b"authenticated".to_vec()))
+            })
+        });
+
+        let roots = TlsConfig::new()
+            .add_root_certificates(&cert)
+            .unwrap()
+            .webpki_roots(false)
+            .alpn_protocols(&[b"http/1.1"]);
+        let get = || http::Request::get(url.as_str()).body(&b""[..]).unwrap();
+
+        let mut client = Client::<TcpStream, _>::with_resolver(TcpResolver::new(), &url).unwrap();
+        client.set_tls_config(roots.clone().build().unwrap());
+        assert!(client.request(get()).is_err());
+
+        let config = roots
+            .client_certificate(&client_cert, &client_key)
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut client = Client::<TcpStream, _>::with_resolver(TcpResolver::new(), &url).unwrap();
+        client.set_tls_config(config);
+        let mut res = client.request(get()).unwrap();
+        let mut body = String::new();
+        res.body_mut().read_to_string(&mut body).unwrap();
+        assert_eq!(body, "authenticated");
+
+        for path in &[cert, key, client_cert, client_key] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[cfg_attr(not(feature = "tcp"), allow(dead_code))]
+    pub(crate) fn serve_tls(cert: &Path, key: &Path) -> u16 {
+        let config = TlsServerConfig::new()
+            .default_certificate(cert, key)
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let listener = TlsListener::new(listener, config.build());
+        std::thread::spawn(move || {
+            Server::new().threads(1).serve(listener, |_| {
+                http::Response::new(Cursor::new(b"hello".to_vec()))
+            })
+        });
+        port
+    }
+
+    #[test]
+    fn ip_server_names() {
+        for ip in &["10.0.0.5", "::1", "2001:db8::8a2e:370:7334"] {
+            let ip = ip.parse().unwrap();
+            let name = ip_server_name(ip);
+            assert!(webpki::DNSNameRef::try_from_ascii_str(&name).is_ok());
+        }
+
+        let stream = TcpStream::connect(
+            TcpListener::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap(),
+        );
+        match HttpStream::tls(stream.unwrap(), "not a host name") {
+            Err(HttpError::Tls(TlsError::InvalidServerName(name))) => {
+                assert_eq!(name, "not a host name")
+            }
+            _ => panic!("the name should be refused"),
+        }
+    }
+
+    #[cfg(feature = "tcp")]
+    #[test]
+    fn ip_address_hosts() {
+        use crate::client::Client;
+        use crate::resolver::TcpResolver;
+
+        let mut params = rcgen::CertificateParams::new(vec!["localhost".to_string()]);
+        params
+            .subject_alt_names
+            .push(rcgen::SanType::IpAddress("127.0.0.1".parse().unwrap()));
+        let with_ip = write_cert(
+            "ip",
+            "with-ip",
+            rcgen::Certificate::from_params(params).unwrap(),
+        );
+        let without_ip = self_signed("ip", "localhost");
+
+        let get = |port: u16, cert: &Path, server_name: Option<&str>| {
+            let url = format!("https://127.0.0.1:{}/", port);
+            let config = TlsConfig::new()
+                .add_root_certificates(cert)
+                .unwrap()
+                .webpki_roots(false)
+                .build()
+                .unwrap();
+            let mut client =
+                Client::<TcpStream, _>::with_resolver(TcpResolver::new(), &url).unwrap();
+            client.set_tls_config(config);
+            if let Some(name) = server_name {
+                client.set_tls_server_name(name);
+            }
+            let req = http::Request::get(url.as_str()).body(&b""[..]).unwrap();
+            client.request(req).map(|res| res.status())
+        };
+
+        let port = serve_tls(&with_ip.0, &with_ip.1);
+        assert_eq!(get(port, &with_ip.0, None).unwrap(), http::StatusCode::OK);
+
+        let port = serve_tls(&without_ip.0, &without_ip.1);
+        assert!(get(port, &without_ip.0, None).is_err());
+        assert_eq!(
+            get(port, &without_ip.0, Some("localhost")).unwrap(),
+            http::StatusCode::OK
+        );
+
+        for (cert, key, _) in &[with_ip, without_ip] {
+            std::fs::remove_file(cert).unwrap();
+            std::fs::remove_file(key).unwrap();
+        }
+    }
+
+    #[cfg(feature = "tcp")]
+    #[test]
+    fn pinning() {
+        use crate::client::Client;
+        use crate::resolver::TcpResolver;
+
+        let (cert, key, der) = self_signed("pin", "localhost");
+        let port = serve_tls(&cert, &key);
+        let url = format!("https://localhost:{}/", port);
+        let pin = spki_sha256(&der).unwrap();
+
+        let get = |config: TlsConfig| {
+            let mut client =
+                Client::<TcpStream, _>::with_resolver(TcpResolver::new(), &url).unwrap();
+            client.set_tls_config(config.build().unwrap());
+            let req = http::Request::get(url.as_str()).body(&b""[..]).unwrap();
+            client.request(req).map(|res| res.status())
+        };
+        let trusted = || {
+            TlsConfig::new()
+                .add_root_certificates(&cert)
+                .unwrap()
+                .webpki_roots(false)
+        };
+
+        assert_eq!(
+            get(trusted().pin_sha256(pin)).unwrap(),
+            http::StatusCode::OK
+        );
+        match get(trusted().pin_sha256([0; 32])) {
+            Err(HttpError::Tls(TlsError::PinMismatch { presented })) => assert_eq!(presented, pin),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let untrusted = || TlsConfig::new().webpki_roots(false);
+        match get(untrusted()) {
+            Err(HttpError::Tls(TlsError::Handshake(_))) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(
+            get(untrusted().dangerous_accept_any_certificate()).unwrap(),
+            http::StatusCode::OK
+        );
+        assert!(get(untrusted()
+            .dangerous_accept_any_certificate()
+            .pin_sha256([0; 32]))
+        .is_err());
+
+        // the copy of the verifier made for IP addresses keeps the pins
+        let url = format!("https://127.0.0.1:{}/", port);
+        let mut client = Client::<TcpStream, _>::with_resolver(TcpResolver::new(), &url).unwrap();
+        let config = untrusted()
+            .dangerous_accept_any_certificate()
+            .pin_sha256([0; 32]);
+        client.set_tls_config(config.build().unwrap());
+        let req = http::Request::get(url.as_str()).body(&b""[..]).unwrap();
+        match client.request(req) {
+            Err(HttpError::Tls(TlsError::PinMismatch { presented })) => assert_eq!(presented, pin),
+            other => panic!("unexpected result: {:?}", other.map(|res| res.status())),
+        }
+
+        std::fs::remove_file(cert).unwrap();
+        std::fs::remove_file(key).unwrap();
+    }
+
+    #[cfg(feature = "tcp")]
+    #[test]
+    fn connection_details() {
+        use crate::client::Client;
+        use crate::resolver::TcpResolver;
+        use crate::stream::RemoteAddr;
+
+        let (cert, key, der) = self_signed("info", "localhost");
+        let port = serve_tls(&cert, &key);
+        let url = format!("https://localhost:{}/", port);
+        let config = TlsConfig::new()
+            .add_root_certificates(&cert)
+            .unwrap()
+            .webpki_roots(false)
+            .alpn_protocols(&[b"h2", b"http/1.1"])
+            .build()
+            .unwrap();
+
+        let mut client = Client::<TcpStream, _>::with_resolver(TcpResolver::new(), &url).unwrap();
+        client.set_tls_config(config);
+        client.record_remote_addr();
+        let req = http::Request::get(url.as_str()).body(&b""[..]).unwrap();
+        let res = client.request(req).unwrap();
+
+        let info = res.extensions().get::<TlsInfo>().unwrap();
+        assert_eq!(info.protocol_version, Some(ProtocolVersion::TLSv1_3));
+        assert!(info.cipher_suite.is_some());
+        assert_eq!(info.alpn_protocol, Some(b"http/1.1".to_vec()));
+        // rcgen signs again each time it serializes, only the key is the same
+        assert_eq!(info.peer_certificates.len(), 1);
+        assert_eq!(spki_sha256(&info.peer_certificates[0]), spki_sha256(&der));
+        assert_eq!(
+            res.extensions().get::<RemoteAddr>().map(|a| a.0.port()),
+            Some(port)
+        );
+
+        std::fs::remove_file(cert).unwrap();
+        std::fs::remove_file(key).unwrap();
+    }
+}
@@ -10,3 +10,193 @@ pub fn eq_no_case(a: &[u8], b: &[u8]) -> bool {
         _ => false,
     })
 }
+
+/// checks if a comma separated header (like `Connection`) contains a token
+pub fn has_token<'a, I: IntoIterator<Item = &'a http::header::HeaderValue>>(
+    values: I,
+    token: &[u8],
+) -> bool {
+    values.into_iter().any(|value| {
+        value
+            .as_bytes()
+            .split(|c| *c == b',')
+            .any(|t| eq_no_case(trim(t), token))
+    })
+}
+
+pub fn trim(mut s: &[u8]) -> &[u8] {
+    while let Some((first, rest)) = s.split_first() {
+        if *first == b' ' || *first == b'\t' {
+            s = rest;
+        } else {
+            break;
+        }
+    }
+    while let Some((last, rest)) = s.split_last() {
+        if *last == b' ' || *last == b'\t' {
+            s = rest;
+        } else {
+            break;
+        }
+    }
+    s
+}
+
+/// converts a header value from the parser, refusing values that are not UTF-8
+pub fn header_value(value: &[u8]) -> Result<&str, crate::HttpError> {
+    std::str::from_utf8(value).map_err(|_| crate::HttpError::InvalidHeaderValue)
+}
+
+/// parses the `Content-Length` headers. A value repeated, in one header or
+/// several, is accepted if it is always the same
+pub fn content_length(headers: &http::HeaderMap) -> Result<Option<usize>, crate::HttpError> {
+    let mut length = None;
+    for value in headers.get_all(http::header::CONTENT_LENGTH) {
+        let value = value
+            .to_str()
+            .map_err(|_| crate::HttpError::InvalidBodyLength)?;
+        for value in value.split(',').map(str::trim) {
+            let nb = Some(value)
+                .filter(|v| !v.is_empty() && v.bytes().all(|b| b.is_ascii_digit()))
+                .and_then(|v| v.parse::<usize>().ok())
+                .ok_or(crate::HttpError::InvalidBodyLength)?;
+            if length.is_some_and(|length| length != nb) {
+                return Err(crate::HttpError::InvalidBodyLength);
+            }
+            length = Some(nb);
+        }
+    }
+    Ok(length)
+}
+
+/// true if the last transfer coding of `Transfer-Encoding` is `chunked`
+pub fn is_chunked(values: http::header::GetAll<http::header::HeaderValue>) -> bool {
+    values
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .rfind(|coding| !coding.is_empty())
+        .is_some_and(|coding| coding.eq_ignore_ascii_case("chunked"))
+}
+
+/// standard base64 with padding, for `Basic` credentials
+pub fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// value of an `Authorization` or `Proxy-Authorization` header for the
+/// `Basic` scheme
+pub fn basic_auth(user: &str, password: &str) -> http::header::HeaderValue {
+    let credentials = base64(format!("{}:{}", user, password).as_bytes());
+    let mut value = http::header::HeaderValue::from_str(&format!("Basic {}", credentials))
+        .expect("base64 is a valid header value");
+    value.set_sensitive(true);
+    value
+}
+
+/// bytes from the random generator of the operating system, `None` if it
+/// failed
+pub fn random<const N: usize>() -> Option<[u8; N]> {
+    let mut bytes = [0u8; N];
+    getrandom::getrandom(&mut bytes).ok()?;
+    Some(bytes)
+}
+
+/// parses a date in the formats found in `Expires` and `Retry-After`, with
+/// the lenient algorithm of RFC 6265 section 5.1.1
+pub fn parse_http_date(date: &str) -> Option<std::time::SystemTime> {
+    const MONTHS: [&str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+    let is_delimiter = |c: char| matches!(c, '\x09' | '\x20'..='\x2f' | '\x3b'..='\x40' | '\x5b'..='\x60' | '\x7b'..='\x7e');
+    // 1 or 2 digits, possibly followed by other characters
+    let number = |token: &str, min: usize, max: usize| -> Option<u32> {
+        let digits = token.len() - token.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        if digits < min || digits > max {
+            return None;
+        }
+        token[..digits].parse().ok()
+    };
+
+    let (mut time, mut day, mut month, mut year) = (None, None, None, None);
+    for token in date.split(is_delimiter).filter(|t| !t.is_empty()) {
+        if time.is_none() {
+            let parts: Vec<&str> = token.splitn(3, ':').collect();
+            if parts.len() == 3 {
+                if let (Some(h), Some(m), Some(s)) = (
+                    number(parts[0], 1, 2).filter(|_| parts[0].len() <= 2),
+                    number(parts[1], 1, 2).filter(|_| parts[1].len() <= 2),
+                    number(parts[2], 1, 2),
+                ) {
+                    time = Some((h, m, s));
+                    continue;
+                }
+            }
+        }
+        if day.is_none() {
+            if let Some(d) = number(token, 1, 2) {
+                day = Some(d);
+                continue;
+            }
+        }
+        if month.is_none() {
+            // `get` fails if the third byte is inside a character
+            let prefix = token.get(..3).map(|p| p.to_ascii_lowercase());
+            if let Some(m) = MONTHS.iter().position(|m| prefix.as_deref() == Some(*m)) {
+                month = Some(m as u32 + 1);
+                continue;
+            }
+        }
+        if year.is_none() {
+            if let Some(y) = number(token, 2, 4) {
+                year = Some(y);
+                continue;
+            }
+        }
+    }
+
+    let (hour, minute, second) = time?;
+    let (day, month, mut year) = (day?, month?, year?);
+    if (70..=99).contains(&year) {
+        year += 1900;
+    } else if year <= 69 {
+        year += 2000;
+    }
+    if !(1..=31).contains(&day) || year < 1601 || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+
+    // days since the epoch, from Howard Hinnant's `days_from_civil`
+    let (y, m, d) = (year as i64 - (month <= 2) as i64, month as i64, day as i64);
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+
+    let seconds = days * 86400 + hour as i64 * 3600 + minute as i64 * 60 + second as i64;
+    if seconds >= 0 {
+        Some(std::time::UNIX_EPOCH + std::time::Duration::from_secs(seconds as u64))
+    } else {
+        Some(std::time::UNIX_EPOCH - std::time::Duration::from_secs(seconds.unsigned_abs()))
+    }
+}
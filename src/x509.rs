@@ -0,0 +1,153 @@
+//! just enough DER parsing to read the parts of a certificate that webpki
+//! does not expose
+
+use std::net::IpAddr;
+
+// subjectAltName, 2.5.29.17
+const SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+
+struct Tlv<'a> {
+    tag: u8,
+    content: &'a [u8],
+    // the whole element, with tag and length
+    raw: &'a [u8],
+}
+
+// reads one element, returns it and the data after it
+fn read_tlv(input: &[u8]) -> Option<(Tlv<'_>, &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let n = (first & 0x7f) as usize;
+        if n == 0 || n > 4 || rest.len() < n {
+            return None;
+        }
+        let len = rest[..n]
+            .iter()
+            .fold(0usize, |acc, b| (acc << 8) | *b as usize);
+        (len, &rest[n..])
+    };
+
+    if rest.len() < len {
+        return None;
+    }
+    let header = input.len() - rest.len();
+    Some((
+        Tlv {
+            tag,
+            content: &rest[..len],
+            raw: &input[..header + len],
+        },
+        &rest[len..],
+    ))
+}
+
+// elements of a SEQUENCE's content
+fn elements(mut input: &[u8]) -> Option<Vec<Tlv<'_>>> {
+    let mut elements = Vec::new();
+    while !input.is_empty() {
+        let (tlv, rest) = read_tlv(input)?;
+        elements.push(tlv);
+        input = rest;
+    }
+    Some(elements)
+}
+
+// fields of the tbsCertificate, without the optional version
+fn tbs_fields(cert: &[u8]) -> Option<Vec<Tlv<'_>>> {
+    let (cert, _) = read_tlv(cert)?;
+    let (tbs, _) = read_tlv(cert.content)?;
+    let mut fields = elements(tbs.content)?;
+    if fields.first().map(|f| f.tag) == Some(0xa0) {
+        fields.remove(0);
+    }
+    Some(fields)
+}
+
+/// the subjectPublicKeyInfo of a DER certificate, as used for pinning
+pub(crate) fn spki(cert: &[u8]) -> Option<&[u8]> {
+    // serial number, signature, issuer, validity, subject, then the key
+    tbs_fields(cert)?.get(5).map(|f| f.raw)
+}
+
+/// IP addresses in the subjectAltName extension of a DER certificate
+pub(crate) fn ip_addresses(cert: &[u8]) -> Vec<IpAddr> {
+    san_ip_addresses(cert).unwrap_or_default()
+}
+
+fn san_ip_addresses(cert: &[u8]) -> Option<Vec<IpAddr>> {
+    let fields = tbs_fields(cert)?;
+    // extensions are explicitly tagged [3]
+    let extensions = fields.iter().find(|f| f.tag == 0xa3)?;
+    let (extensions, _) = read_tlv(extensions.content)?;
+
+    for extension in elements(extensions.content)? {
+        let parts = elements(extension.content)?;
+        if parts.first()?.content != SUBJECT_ALT_NAME {
+            continue;
+        }
+
+        // the value is the last part, after the optional `critical` flag
+        let (names, _) = read_tlv(parts.last()?.content)?;
+        let addresses = elements(names.content)?
+            .into_iter()
+            // iPAddress [7]
+            .filter(|name| name.tag == 0x87)
+            .filter_map(|name| match name.content.len() {
+                4 => {
+                    let mut octets = [0u8; 4];
+                    octets.copy_from_slice(name.content);
+                    Some(IpAddr::from(octets))
+                }
+                16 => {
+                    let mut octets = [0u8; 16];
+                    octets.copy_from_slice(name.content);
+                    Some(IpAddr::from(octets))
+                }
+                _ => None,
+            })
+            .collect();
+        return Some(addresses);
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subject_alt_names() {
+        let mut params = rcgen::CertificateParams::new(vec!["localhost".to_string()]);
+        params
+            .subject_alt_names
+            .push(rcgen::SanType::IpAddress("127.0.0.1".parse().unwrap()));
+        params
+            .subject_alt_names
+            .push(rcgen::SanType::IpAddress("::1".parse().unwrap()));
+        let cert = rcgen::Certificate::from_params(params).unwrap();
+
+        assert_eq!(
+            ip_addresses(&cert.serialize_der().unwrap()),
+            vec![
+                "127.0.0.1".parse::<IpAddr>().unwrap(),
+                "::1".parse::<IpAddr>().unwrap()
+            ]
+        );
+
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        assert!(ip_addresses(&cert.serialize_der().unwrap()).is_empty());
+        assert!(ip_addresses(b"\x30\x82\xff").is_empty());
+
+        let key = rcgen::KeyPair::generate(&rcgen::PKCS_ECDSA_P256_SHA256).unwrap();
+        let spki_der = key.public_key_der();
+        let mut params = rcgen::CertificateParams::new(vec!["localhost".to_string()]);
+        params.key_pair = Some(key);
+        let cert = rcgen::Certificate::from_params(params).unwrap();
+        assert_eq!(spki(&cert.serialize_der().unwrap()), Some(&spki_der[..]));
+    }
+}